@@ -0,0 +1,150 @@
+//! Measures how many queries per second the server answers while forwarding to a slow
+//! upstream, for an increasing number of clients querying in parallel.
+//!
+//! Run with `cargo bench`. The server binds 127.0.0.1:2053, which has to be free.
+
+use std::{
+    net::UdpSocket,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const SERVER_ADDRESS: &str = "127.0.0.1:2053";
+/// Simulated round trip to the upstream resolver.
+const UPSTREAM_DELAY: Duration = Duration::from_millis(20);
+const RUN_TIME: Duration = Duration::from_secs(3);
+const CLIENT_COUNTS: [usize; 4] = [1, 8, 32, 128];
+
+/// Answers every query with the question and a single A record after `UPSTREAM_DELAY`.
+fn spawn_upstream() -> String {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").expect("Failed to bind upstream"));
+    let address = socket.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        loop {
+            let Ok((size, source)) = socket.recv_from(&mut buf) else {
+                return;
+            };
+            let mut response = buf[..size].to_vec();
+            let socket = Arc::clone(&socket);
+            thread::spawn(move || {
+                thread::sleep(UPSTREAM_DELAY);
+                // QR and RA set, one answer, nothing in the other sections
+                response[2] |= 0x80;
+                response[3] = 0x80;
+                response[6..12].copy_from_slice(&[0, 1, 0, 0, 0, 0]);
+                response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                response.extend_from_slice(&[192, 0, 2, 1]);
+                let _ = socket.send_to(&response, source);
+            });
+        }
+    });
+
+    address
+}
+
+fn query(id: u16) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+    query
+}
+
+/// Sends queries back to back for `RUN_TIME` from each of `clients` threads and returns the
+/// number of answers received per second.
+fn measure(clients: usize) -> f64 {
+    let answered = Arc::new(AtomicUsize::new(0));
+    let running = Arc::new(AtomicBool::new(true));
+
+    let threads: Vec<_> = (0..clients)
+        .map(|_| {
+            let answered = Arc::clone(&answered);
+            let running = Arc::clone(&running);
+            thread::spawn(move || {
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                socket.connect(SERVER_ADDRESS).unwrap();
+                socket
+                    .set_read_timeout(Some(Duration::from_secs(1)))
+                    .unwrap();
+                let mut buf = [0; 512];
+                let mut id: u16 = 0;
+                while running.load(Ordering::Relaxed) {
+                    id = id.wrapping_add(1);
+                    socket.send(&query(id)).unwrap();
+                    if matches!(socket.recv(&mut buf), Ok(size) if size >= 12 && buf[..2] == id.to_be_bytes())
+                    {
+                        answered.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    thread::sleep(RUN_TIME);
+    running.store(false, Ordering::Relaxed);
+    let elapsed = start.elapsed();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    answered.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64()
+}
+
+/// Waits until the server answers, so startup time doesn't count against the first run.
+fn wait_for_server() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let mut buf = [0; 512];
+    for _ in 0..50 {
+        socket.send_to(&query(0), SERVER_ADDRESS).unwrap();
+        if socket.recv(&mut buf).is_ok() {
+            return;
+        }
+    }
+    panic!("server did not start answering");
+}
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn main() {
+    let upstream = spawn_upstream();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_codecrafters-dns-server"))
+            // Every query should reach the upstream, not be answered from the cache
+            .args(["--resolver", &upstream, "--cache-size", "0"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start server"),
+    );
+    wait_for_server();
+
+    println!(
+        "forwarding with {}ms upstream latency, {}s per run",
+        UPSTREAM_DELAY.as_millis(),
+        RUN_TIME.as_secs()
+    );
+    for clients in CLIENT_COUNTS {
+        println!(
+            "{:>4} clients: {:>8.0} queries/s",
+            clients,
+            measure(clients)
+        );
+    }
+}
@@ -1,14 +1,54 @@
+use super::dns_error::DnsParseError;
+
+/// Largest message a plain (non-EDNS) UDP exchange may carry.
+pub const UDP_MESSAGE_SIZE: usize = 512;
+/// Largest message the 16-bit TCP length prefix can describe.
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
 #[derive(Debug)]
 pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
-    pub position: u8,
+    pub buf: Vec<u8>,
+    /// Number of bytes at the start of `buf` that hold the received message.
+    pub position: usize,
 }
 
 impl BytePacketBuffer {
     pub fn new() -> Self {
+        Self::with_max_size(UDP_MESSAGE_SIZE)
+    }
+
+    pub fn with_max_size(max_size: usize) -> Self {
         Self {
-            buf: [0; 512],
+            buf: vec![0; max_size],
             position: 0,
         }
     }
+
+    pub fn filled(&self) -> &[u8] {
+        &self.buf[..self.position]
+    }
+}
+
+/// Returns `len` bytes starting at `start`, or an error if the input is too short.
+pub fn read_slice(input: &[u8], start: usize, len: usize) -> Result<&[u8], DnsParseError> {
+    input
+        .get(start..start + len)
+        .ok_or(DnsParseError::UnexpectedEnd(start))
+}
+
+pub fn read_u8(input: &[u8], index: usize) -> Result<u8, DnsParseError> {
+    input
+        .get(index)
+        .copied()
+        .ok_or(DnsParseError::UnexpectedEnd(index))
+}
+
+pub fn read_u16(input: &[u8], index: usize) -> Result<u16, DnsParseError> {
+    let bytes = read_slice(input, index, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(input: &[u8], index: usize) -> Result<u32, DnsParseError> {
+    let bytes = read_slice(input, index, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
@@ -0,0 +1,81 @@
+use super::dns_error::DnsParseError;
+use super::dns_header::ResponseCode;
+use super::dns_message::ResourceRecord;
+use super::dns_question::{DomainName, ResourceClass};
+use super::dns_rdata::RData;
+
+/// The only EDNS version defined so far.
+pub const EDNS_VERSION: u8 = 0;
+/// Payload size we advertise by default, the value agreed on for DNS Flag Day 2020.
+pub const DEFAULT_EDNS_UDP_SIZE: u16 = 1232;
+
+/// EDNS(0) information carried in the OPT pseudo-record of the additional section (RFC 6891).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    /// Largest UDP payload the sender can reassemble.
+    pub udp_payload_size: u16,
+    /// Upper eight bits of the 12-bit RCODE; the lower four live in the header.
+    pub extended_rcode: u8,
+    pub version: u8,
+    /// "DNSSEC OK" (RFC 3225).
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Edns {
+    const DO_MASK: u32 = 0b1000_0000_0000_0000;
+
+    pub fn new(udp_payload_size: u16) -> Self {
+        Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: EDNS_VERSION,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+
+    /// Reads the EDNS fields out of an OPT record. The CLASS holds the payload size and the
+    /// TTL holds the extended RCODE, version and flags.
+    pub fn from_record(record: &ResourceRecord) -> Result<Self, DnsParseError> {
+        let RData::OPT(options) = &record.data else {
+            return Err(DnsParseError::InvalidOpt("record is not of type OPT"));
+        };
+        if !record.name.content.is_empty() {
+            return Err(DnsParseError::InvalidOpt("owner name must be the root"));
+        }
+
+        let ttl = record.ttl.to_be_bytes();
+        Ok(Edns {
+            udp_payload_size: record.resource_class.value(),
+            extended_rcode: ttl[0],
+            version: ttl[1],
+            dnssec_ok: record.ttl & Self::DO_MASK != 0,
+            options: options.clone(),
+        })
+    }
+
+    pub fn to_record(&self) -> ResourceRecord {
+        let mut ttl = u32::from_be_bytes([self.extended_rcode, self.version, 0, 0]);
+        if self.dnssec_ok {
+            ttl |= Self::DO_MASK;
+        }
+        ResourceRecord {
+            name: DomainName::new(),
+            resource_class: ResourceClass::from(self.udp_payload_size),
+            ttl,
+            data: RData::OPT(self.options.clone()),
+        }
+    }
+
+    /// Combines the header's 4-bit RCODE with the extended bits into the full value.
+    pub fn full_response_code(&self, header_rcode: ResponseCode) -> ResponseCode {
+        ResponseCode::from(((self.extended_rcode as u16) << 4) | header_rcode.value())
+    }
+}
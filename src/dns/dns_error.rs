@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+/// Everything that can go wrong while turning wire bytes into DNS structures.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DnsParseError {
+    #[error("header must be exactly 12 bytes, got {0}")]
+    InvalidHeaderLength(usize),
+    #[error("message ended unexpectedly at offset {0}")]
+    UnexpectedEnd(usize),
+    #[error("operation code {0} is not valid")]
+    InvalidOpCode(u8),
+    #[error("compression pointer at offset {0} does not point to an earlier name")]
+    ForwardPointer(usize),
+    #[error("domain name starting at offset {0} follows too many compression pointers")]
+    TooManyPointers(usize),
+    #[error("label at offset {0} uses a reserved label type")]
+    InvalidLabelType(usize),
+    #[error("domain name starting at offset {0} is longer than 255 bytes")]
+    NameTooLong(usize),
+    #[error("record data at offset {0} does not match its declared length")]
+    RDataLength(usize),
+    #[error("invalid OPT record: {0}")]
+    InvalidOpt(&'static str),
+}
+
+/// Everything that can go wrong while turning DNS structures into wire bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DnsSerializeError {
+    #[error("message needs {size} bytes but at most {max} are allowed")]
+    MessageTooLarge { size: usize, max: usize },
+}
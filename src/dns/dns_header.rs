@@ -1,4 +1,6 @@
-#[derive(Debug, Clone)]
+use super::dns_error::DnsParseError;
+
+#[derive(Debug, Clone, Default)]
 pub struct DnsHeader {
     packet_identifier: u16,
     pub flags: u16,
@@ -8,10 +10,12 @@ pub struct DnsHeader {
     pub additional_record_count: u16,
 }
 
-impl From<&[u8]> for DnsHeader {
-    fn from(bytes: &[u8]) -> Self {
+impl TryFrom<&[u8]> for DnsHeader {
+    type Error = DnsParseError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
         if bytes.len() != 12 {
-            panic!("only slices of length 12 can be converted into a DnsHeader");
+            return Err(DnsParseError::InvalidHeaderLength(bytes.len()));
         }
         let packet_identifier = u16::from_be_bytes(bytes[0..=1].try_into().unwrap());
         let flags = u16::from_be_bytes(bytes[2..=3].try_into().unwrap());
@@ -19,52 +23,186 @@ impl From<&[u8]> for DnsHeader {
         let answer_record_count = u16::from_be_bytes(bytes[6..=7].try_into().unwrap());
         let authority_record_count = u16::from_be_bytes(bytes[8..=9].try_into().unwrap());
         let additional_record_count = u16::from_be_bytes(bytes[10..=11].try_into().unwrap());
-        DnsHeader {
+        Ok(DnsHeader {
             packet_identifier,
             flags,
             question_count,
             answer_record_count,
             authority_record_count,
             additional_record_count,
-        }
+        })
     }
 }
 
-impl Into<[u8; 12]> for DnsHeader {
-    fn into(self) -> [u8; 12] {
+impl From<DnsHeader> for [u8; 12] {
+    fn from(header: DnsHeader) -> Self {
         let mut bytes = [0u8; 12];
 
-        bytes[0..=1].copy_from_slice(&self.packet_identifier.to_be_bytes());
-        bytes[2..=3].copy_from_slice(&self.flags.to_be_bytes());
-        bytes[4..=5].copy_from_slice(&self.question_count.to_be_bytes());
-        bytes[6..=7].copy_from_slice(&self.answer_record_count.to_be_bytes());
-        bytes[8..=9].copy_from_slice(&self.authority_record_count.to_be_bytes());
-        bytes[10..=11].copy_from_slice(&self.additional_record_count.to_be_bytes());
+        bytes[0..=1].copy_from_slice(&header.packet_identifier.to_be_bytes());
+        bytes[2..=3].copy_from_slice(&header.flags.to_be_bytes());
+        bytes[4..=5].copy_from_slice(&header.question_count.to_be_bytes());
+        bytes[6..=7].copy_from_slice(&header.answer_record_count.to_be_bytes());
+        bytes[8..=9].copy_from_slice(&header.authority_record_count.to_be_bytes());
+        bytes[10..=11].copy_from_slice(&header.additional_record_count.to_be_bytes());
         bytes
     }
 }
 
 impl DnsHeader {
+    // Positions of the single-bit flags within `flags`
+    const QR_MASK: u16 = 0b1000_0000_0000_0000;
+    const OPCODE_MASK: u16 = 0b0111_1000_0000_0000;
+    const AA_MASK: u16 = 0b0000_0100_0000_0000;
+    const TC_MASK: u16 = 0b0000_0010_0000_0000;
+    const RD_MASK: u16 = 0b0000_0001_0000_0000;
+    const RA_MASK: u16 = 0b0000_0000_1000_0000;
+    const Z_MASK: u16 = 0b0000_0000_0100_0000;
+    const AD_MASK: u16 = 0b0000_0000_0010_0000;
+    const CD_MASK: u16 = 0b0000_0000_0001_0000;
+    const RCODE_MASK: u16 = 0b0000_0000_0000_1111;
+
+    pub fn builder() -> DnsHeaderBuilder {
+        DnsHeaderBuilder::new()
+    }
+
+    pub fn get_packet_identifier(&self) -> u16 {
+        self.packet_identifier
+    }
+
+    pub fn set_packet_identifier(&mut self, packet_identifier: u16) {
+        self.packet_identifier = packet_identifier;
+    }
+
     pub fn set_header_flag(&mut self, flag: DnsHeaderFlag) {
         match flag {
             DnsHeaderFlag::Qr(qri) => match qri {
                 // Ensures flag is set to '0' regardless of whether current value is 1 or 0
-                QueryResponseIndicator::Query() => self.flags &= qri.value(),
+                QueryResponseIndicator::Query() => self.flags &= !Self::QR_MASK,
                 // Ensures flag is set to '1' regardless of whether current value is 1 or 0
                 QueryResponseIndicator::Response() => self.flags |= qri.value(),
             },
+            DnsHeaderFlag::OpCode(op_code) => {
+                // clear the op code bits
+                self.flags &= !Self::OPCODE_MASK;
+                self.flags |= ((op_code.value() as u16) << 11) & Self::OPCODE_MASK;
+            }
+            DnsHeaderFlag::Aa(value) => self.set_bit(Self::AA_MASK, value),
+            DnsHeaderFlag::Tc(value) => self.set_bit(Self::TC_MASK, value),
+            DnsHeaderFlag::Rd(value) => self.set_bit(Self::RD_MASK, value),
+            DnsHeaderFlag::Ra(value) => self.set_bit(Self::RA_MASK, value),
+            // The only defined value of Z is zero
+            DnsHeaderFlag::Z(Reserved::Unassigned()) => self.set_bit(Self::Z_MASK, false),
+            DnsHeaderFlag::Ad(value) => self.set_bit(Self::AD_MASK, value),
+            DnsHeaderFlag::Cd(value) => self.set_bit(Self::CD_MASK, value),
             DnsHeaderFlag::RCode(code) => {
                 // clear the response code bits
-                self.flags &= 0b1111111111110000;
-                self.flags |= (code as u16) & 0b0000000000001111;
+                self.flags &= !Self::RCODE_MASK;
+                self.flags |= code.value() & Self::RCODE_MASK;
             }
-            _ => {}
         }
     }
+
+    pub fn get_query_response_indicator(&self) -> QueryResponseIndicator {
+        if self.flags & Self::QR_MASK != 0 {
+            QueryResponseIndicator::Response()
+        } else {
+            QueryResponseIndicator::Query()
+        }
+    }
+
     pub fn get_op_code(&self) -> OperationCode {
         // isolate the op code bits and convert to a u8 by shifting
-        let op_bits = (self.flags & 0b0111100000000000) >> 11;
-        OperationCode::try_from(op_bits as u8).unwrap()
+        let op_bits = (self.flags & Self::OPCODE_MASK) >> 11;
+        OperationCode::try_from(op_bits as u8).expect("op code is only four bits wide")
+    }
+
+    pub fn get_authoritative_answer(&self) -> bool {
+        self.flags & Self::AA_MASK != 0
+    }
+
+    pub fn get_truncation(&self) -> bool {
+        self.flags & Self::TC_MASK != 0
+    }
+
+    pub fn get_recursion_desired(&self) -> bool {
+        self.flags & Self::RD_MASK != 0
+    }
+
+    pub fn get_recursion_available(&self) -> bool {
+        self.flags & Self::RA_MASK != 0
+    }
+
+    /// The reserved Z bit, which should be zero but is reported as received.
+    pub fn get_z(&self) -> bool {
+        self.flags & Self::Z_MASK != 0
+    }
+
+    pub fn get_authentic_data(&self) -> bool {
+        self.flags & Self::AD_MASK != 0
+    }
+
+    pub fn get_checking_disabled(&self) -> bool {
+        self.flags & Self::CD_MASK != 0
+    }
+
+    /// The 4-bit RCODE carried in the header. EDNS can extend it beyond 15.
+    pub fn get_response_code(&self) -> ResponseCode {
+        ResponseCode::from(self.flags & Self::RCODE_MASK)
+    }
+
+    fn set_bit(&mut self, mask: u16, value: bool) {
+        if value {
+            self.flags |= mask;
+        } else {
+            self.flags &= !mask;
+        }
+    }
+}
+
+/// Builds a `DnsHeader` from scratch. Everything not set explicitly is zero, which makes an
+/// empty standard query.
+#[derive(Debug, Clone, Default)]
+pub struct DnsHeaderBuilder {
+    header: DnsHeader,
+}
+
+impl DnsHeaderBuilder {
+    pub fn new() -> Self {
+        DnsHeaderBuilder::default()
+    }
+
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> Self {
+        self.header.packet_identifier = packet_identifier;
+        self
+    }
+
+    pub fn flag(mut self, flag: DnsHeaderFlag) -> Self {
+        self.header.set_header_flag(flag);
+        self
+    }
+
+    pub fn question_count(mut self, count: u16) -> Self {
+        self.header.question_count = count;
+        self
+    }
+
+    pub fn answer_record_count(mut self, count: u16) -> Self {
+        self.header.answer_record_count = count;
+        self
+    }
+
+    pub fn authority_record_count(mut self, count: u16) -> Self {
+        self.header.authority_record_count = count;
+        self
+    }
+
+    pub fn additional_record_count(mut self, count: u16) -> Self {
+        self.header.additional_record_count = count;
+        self
+    }
+
+    pub fn build(self) -> DnsHeader {
+        self.header
     }
 }
 
@@ -77,10 +215,12 @@ pub enum DnsHeaderFlag {
     Rd(bool),
     Ra(bool),
     Z(Reserved),
+    Ad(bool),
+    Cd(bool),
     RCode(ResponseCode),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum QueryResponseIndicator {
     Query(),
     Response(),
@@ -95,43 +235,44 @@ impl QueryResponseIndicator {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Declared in IANA order; `Unassigned` keeps the raw value of opcodes 3 and 7-15.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OperationCode {
     Query(),
     IQuery(),
-    Unassigned(),
     Status(),
+    Unassigned(u8),
     Notify(),
     Update(),
     DnsStatefulOperations(),
 }
 
 impl OperationCode {
-    // fn value(&self) -> u8 {
-    //     match *self {
-    //         OperationCode::Query() => 0,
-    //         OperationCode::IQuery() => 1,
-    //         OperationCode::Unassigned() => 2,
-    //         OperationCode::Status() => 3,
-    //         OperationCode::Notify() => 4,
-    //         OperationCode::Update() => 5,
-    //         OperationCode::DnsStatefulOperations() => 6,
-    //     }
-    // }
+    pub fn value(&self) -> u8 {
+        match *self {
+            OperationCode::Query() => 0,
+            OperationCode::IQuery() => 1,
+            OperationCode::Status() => 2,
+            OperationCode::Unassigned(value) => value,
+            OperationCode::Notify() => 4,
+            OperationCode::Update() => 5,
+            OperationCode::DnsStatefulOperations() => 6,
+        }
+    }
 }
 
 impl TryFrom<u8> for OperationCode {
-    type Error = String;
+    type Error = DnsParseError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(OperationCode::Query()),
             1 => Ok(OperationCode::IQuery()),
             2 => Ok(OperationCode::Status()),
-            3 => Ok(OperationCode::Unassigned()),
             4 => Ok(OperationCode::Notify()),
             5 => Ok(OperationCode::Update()),
             6 => Ok(OperationCode::DnsStatefulOperations()),
-            _ => unreachable!(),
+            3 | 7..=15 => Ok(OperationCode::Unassigned(value)),
+            _ => Err(DnsParseError::InvalidOpCode(value)),
         }
     }
 }
@@ -141,28 +282,120 @@ pub enum Reserved {
     Unassigned(),
 }
 
-#[derive(Debug, Clone)]
+/// Declared in IANA order; `Unassigned` keeps the raw value of every RCODE without a name
+/// of its own, so it survives being passed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ResponseCode {
-    NoError = 0,      // No Error [RFC1035]
-    FormErr = 1,      // Format Error [RFC1035]
-    ServFail = 2,     // Server Failure [RFC1035]
-    NXDomain = 3,     // Non-Existent Domain [RFC1035]
-    NotImp = 4,       // Not Implemented [RFC1035]
-    Refused = 5,      // Query Refused [RFC1035]
-    YXDomain = 6,     // Name Exists when it should not [RFC2136][RFC6672]
-    YXRRSet = 7,      // RR Set Exists when it should not [RFC2136]
-    NXRRSet = 8,      // RR Set that should exist does not [RFC2136]
-    NotAuth = 9,      // Not Authorized [RFC8945]; Server Not Authoritative for zone [RFC2136]
-    NotZone = 10,     // Name not contained in zone [RFC2136]
-    DSOTYPENI = 11,   // DSO-TYPE Not Implemented [RFC8490]
-    Unassigned = 12,  // Unassigned
-    BADVERS = 16,     // Bad OPT Version [RFC6891]; TSIG Signature Failure [RFC8945]
-    BADKEY = 17,      // Key not recognized [RFC8945]
-    BADTIME = 18,     // Signature out of time window [RFC8945]
-    BADMODE = 19,     // Bad TKEY Mode [RFC2930]
-    BADNAME = 20,     // Duplicate key name [RFC2930]
-    BADALG = 21,      // Algorithm not supported [RFC2930]
-    BADTRUNC = 22,    // Bad Truncation [RFC8945]
-    BADCOOKIE = 23,   // Bad/missing Server Cookie [RFC7873]
-    Reserved = 65535, // Reserved, can be allocated by Standards Action
+    NoError,         // No Error [RFC1035]
+    FormErr,         // Format Error [RFC1035]
+    ServFail,        // Server Failure [RFC1035]
+    NXDomain,        // Non-Existent Domain [RFC1035]
+    NotImp,          // Not Implemented [RFC1035]
+    Refused,         // Query Refused [RFC1035]
+    YXDomain,        // Name Exists when it should not [RFC2136][RFC6672]
+    YXRRSet,         // RR Set Exists when it should not [RFC2136]
+    NXRRSet,         // RR Set that should exist does not [RFC2136]
+    NotAuth,         // Not Authorized [RFC8945]; Server Not Authoritative for zone [RFC2136]
+    NotZone,         // Name not contained in zone [RFC2136]
+    DSOTYPENI,       // DSO-TYPE Not Implemented [RFC8490]
+    Unassigned(u16), // Unassigned
+    BADVERS,         // Bad OPT Version [RFC6891]; TSIG Signature Failure [RFC8945]
+    BADKEY,          // Key not recognized [RFC8945]
+    BADTIME,         // Signature out of time window [RFC8945]
+    BADMODE,         // Bad TKEY Mode [RFC2930]
+    BADNAME,         // Duplicate key name [RFC2930]
+    BADALG,          // Algorithm not supported [RFC2930]
+    BADTRUNC,        // Bad Truncation [RFC8945]
+    BADCOOKIE,       // Bad/missing Server Cookie [RFC7873]
+    Reserved,        // Reserved, can be allocated by Standards Action
+}
+
+impl ResponseCode {
+    pub fn value(&self) -> u16 {
+        match *self {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormErr => 1,
+            ResponseCode::ServFail => 2,
+            ResponseCode::NXDomain => 3,
+            ResponseCode::NotImp => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::YXDomain => 6,
+            ResponseCode::YXRRSet => 7,
+            ResponseCode::NXRRSet => 8,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::NotZone => 10,
+            ResponseCode::DSOTYPENI => 11,
+            ResponseCode::Unassigned(value) => value,
+            ResponseCode::BADVERS => 16,
+            ResponseCode::BADKEY => 17,
+            ResponseCode::BADTIME => 18,
+            ResponseCode::BADMODE => 19,
+            ResponseCode::BADNAME => 20,
+            ResponseCode::BADALG => 21,
+            ResponseCode::BADTRUNC => 22,
+            ResponseCode::BADCOOKIE => 23,
+            ResponseCode::Reserved => 65535,
+        }
+    }
+}
+
+impl From<u16> for ResponseCode {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormErr,
+            2 => ResponseCode::ServFail,
+            3 => ResponseCode::NXDomain,
+            4 => ResponseCode::NotImp,
+            5 => ResponseCode::Refused,
+            6 => ResponseCode::YXDomain,
+            7 => ResponseCode::YXRRSet,
+            8 => ResponseCode::NXRRSet,
+            9 => ResponseCode::NotAuth,
+            10 => ResponseCode::NotZone,
+            11 => ResponseCode::DSOTYPENI,
+            16 => ResponseCode::BADVERS,
+            17 => ResponseCode::BADKEY,
+            18 => ResponseCode::BADTIME,
+            19 => ResponseCode::BADMODE,
+            20 => ResponseCode::BADNAME,
+            21 => ResponseCode::BADALG,
+            22 => ResponseCode::BADTRUNC,
+            23 => ResponseCode::BADCOOKIE,
+            65535 => ResponseCode::Reserved,
+            _ => ResponseCode::Unassigned(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_clears_the_z_bit() {
+        let mut bytes = [0u8; 12];
+        bytes[3] = 0b0100_0000;
+        let mut header = DnsHeader::try_from(&bytes[..]).unwrap();
+        assert!(header.get_z());
+        assert!(!header.get_authentic_data() && !header.get_checking_disabled());
+
+        header.set_header_flag(DnsHeaderFlag::Z(Reserved::Unassigned()));
+        assert!(!header.get_z());
+    }
+
+    #[test]
+    fn keeps_unassigned_response_codes() {
+        for value in [12, 13, 15, 24, 3840] {
+            assert_eq!(ResponseCode::from(value), ResponseCode::Unassigned(value));
+            assert_eq!(ResponseCode::from(value).value(), value);
+        }
+        assert_eq!(ResponseCode::from(16), ResponseCode::BADVERS);
+
+        let mut bytes = [0u8; 12];
+        bytes[3] = 14;
+        let header = DnsHeader::try_from(&bytes[..]).unwrap();
+        assert_eq!(header.get_response_code(), ResponseCode::Unassigned(14));
+    }
 }
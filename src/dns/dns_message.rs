@@ -1,58 +1,111 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use bytes::{BufMut, BytesMut};
+
 use crate::dns::dns_header::DnsHeader;
 use crate::dns::dns_question::Question;
 
-use super::dns_question::{DomainName, ResourceClass, ResourceType};
+use super::buffer_packets::{read_u16, read_u32, UDP_MESSAGE_SIZE};
+use super::dns_edns::Edns;
+use super::dns_error::{DnsParseError, DnsSerializeError};
+use super::dns_header::{DnsHeaderFlag, QueryResponseIndicator, ResponseCode};
+use super::dns_question::{DomainName, NameCompressor, ResourceClass, ResourceType};
+use super::dns_rdata::RData;
+
+/// Knobs for `DnsMessage::serialize_with`.
+#[derive(Debug, Clone)]
+pub struct SerializeOptions {
+    /// Emit compression pointers for repeated names. Turning this off makes packet dumps
+    /// easier to read.
+    pub compression: bool,
+    /// Serializing fails with `MessageTooLarge` if the message would exceed this many bytes.
+    pub max_size: usize,
+}
+
+impl Default for SerializeOptions {
+    fn default() -> Self {
+        SerializeOptions {
+            compression: true,
+            max_size: UDP_MESSAGE_SIZE,
+        }
+    }
+}
 
+#[derive(Debug, Clone)]
 pub struct DnsMessage {
     pub header: DnsHeader,
     pub questions: Vec<Question>,
-    pub answers: Vec<Answer>,
-    pub authority: Vec<u8>,
-    pub extra: Vec<u8>,
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub extra: Vec<ResourceRecord>,
+    /// Taken out of (and written back to the end of) the additional section.
+    pub edns: Option<Edns>,
 }
 
-impl From<&[u8; 512]> for DnsMessage {
-    fn from(message: &[u8; 512]) -> Self {
-        let header = DnsHeader::from(&message[..=11]);
+impl TryFrom<&[u8]> for DnsMessage {
+    type Error = DnsParseError;
+
+    fn try_from(message: &[u8]) -> Result<Self, Self::Error> {
+        let header_bytes = message
+            .get(..12)
+            .ok_or(DnsParseError::InvalidHeaderLength(message.len()))?;
+        let header = DnsHeader::try_from(header_bytes)?;
 
         let question_count = header.question_count;
 
-        let (questions, q_section_len) =
-            DnsMessage::parse_question_section(&message[12..], question_count).unwrap();
+        // Sections are parsed against the whole message so compression pointers resolve
+        let (questions, answer_offset) =
+            DnsMessage::parse_question_section(message, 12, question_count)?;
 
         let answer_count = header.answer_record_count;
-        let (answers, _a_section_len) =
-            DnsMessage::parse_answer_section(&message[12 + q_section_len..], answer_count).unwrap();
+        let (answers, authority_offset) =
+            DnsMessage::parse_record_section(message, answer_offset, answer_count)?;
 
-        DnsMessage {
+        let authority_count = header.authority_record_count;
+        let (authority, additional_offset) =
+            DnsMessage::parse_record_section(message, authority_offset, authority_count)?;
+
+        let additional_count = header.additional_record_count;
+        let (mut extra, _end_offset) =
+            DnsMessage::parse_record_section(message, additional_offset, additional_count)?;
+
+        // At most one OPT record is allowed, and only in the additional section
+        let mut opt_records = extra
+            .iter()
+            .filter(|r| r.resource_type() == ResourceType::OPT);
+        let edns = match (opt_records.next(), opt_records.next()) {
+            (None, _) => None,
+            (Some(opt), None) => Some(Edns::from_record(opt)?),
+            (Some(_), Some(_)) => return Err(DnsParseError::InvalidOpt("more than one present")),
+        };
+        extra.retain(|r| r.resource_type() != ResourceType::OPT);
+
+        Ok(DnsMessage {
             header,
             questions,
             answers,
-            authority: Vec::with_capacity(512),
-            extra: Vec::with_capacity(512),
-        }
+            authority,
+            extra,
+            edns,
+        })
     }
 }
 
 impl DnsMessage {
+    /// Parses `count` questions starting at `offset` of the full message and returns them
+    /// with the offset of the first byte after the section.
     pub fn parse_question_section(
-        input: &[u8],
+        message: &[u8],
+        offset: usize,
         count: u16,
-    ) -> Result<(Vec<Question>, usize), String> {
+    ) -> Result<(Vec<Question>, usize), DnsParseError> {
         let mut questions = Vec::<Question>::new();
         let count_size = count as usize;
 
-        if input.is_empty() {
-            return Ok((questions, 0));
-        }
-
-        let mut offset = 0;
+        let mut offset = offset;
         for _i in 0..(count_size) {
-            // One byte for the '0' terminator, two bytes for the type, two bytes for the class
-            // let end = end_points[i] + 5;
-
-            // let q = Question::from(&input[offset..end]);
-            let (q, q_end_index) = Question::deserialize(&input, offset);
+            let (q, q_end_index) = Question::deserialize(message, offset)?;
             questions.push(q);
             offset = q_end_index + 1;
         }
@@ -60,147 +113,471 @@ impl DnsMessage {
         Ok((questions, offset))
     }
 
-    pub fn parse_answer_section(input: &[u8], count: u16) -> Result<(Vec<Answer>, usize), String> {
-        let mut answers = Vec::<Answer>::new();
+    /// Parses `count` records of the answer, authority or additional section starting at
+    /// `offset` of the full message and returns them with the offset of the first byte after
+    /// the section.
+    pub fn parse_record_section(
+        message: &[u8],
+        offset: usize,
+        count: u16,
+    ) -> Result<(Vec<ResourceRecord>, usize), DnsParseError> {
+        let mut records = Vec::<ResourceRecord>::new();
         let count_usize = count as usize;
-        if input.is_empty() {
-            return Ok((answers, 0));
-        }
-        let mut offset = 0;
+        let mut offset = offset;
         for _i in 0..count_usize {
-            let (a, a_end_index) = Answer::deserialize(&input, offset);
-            answers.push(a);
-            offset = a_end_index + 1;
+            let (record, record_end_index) = ResourceRecord::deserialize(message, offset)?;
+            records.push(record);
+            offset = record_end_index + 1;
+        }
+        Ok((records, offset))
+    }
+
+    /// A message asking just `question`, with a zero ID and no flags set.
+    pub fn query(question: Question) -> Self {
+        DnsMessage {
+            header: DnsHeader::builder().question_count(1).build(),
+            questions: vec![question],
+            answers: vec![],
+            authority: vec![],
+            extra: vec![],
+            edns: None,
+        }
+    }
+
+    /// Builds a header-only reply to `header` carrying `code`, used when the request
+    /// can't be answered normally (e.g. it failed to parse).
+    pub fn error_response(mut header: DnsHeader, code: ResponseCode) -> Self {
+        header.question_count = 0;
+        header.answer_record_count = 0;
+        header.authority_record_count = 0;
+        header.additional_record_count = 0;
+        header.set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Response()));
+        header.set_header_flag(DnsHeaderFlag::RCode(code));
+
+        DnsMessage {
+            header,
+            questions: vec![],
+            answers: vec![],
+            authority: vec![],
+            extra: vec![],
+            edns: None,
+        }
+    }
+
+    /// The full RCODE, including the upper bits carried by EDNS.
+    pub fn get_response_code(&self) -> ResponseCode {
+        let header_rcode = self.header.get_response_code();
+        match &self.edns {
+            Some(edns) => edns.full_response_code(header_rcode),
+            None => header_rcode,
+        }
+    }
+
+    /// Sets the RCODE, splitting codes above 15 between the header and EDNS. Without EDNS
+    /// only the lower four bits can be sent.
+    pub fn set_response_code(&mut self, code: ResponseCode) {
+        self.header.set_header_flag(DnsHeaderFlag::RCode(code));
+        if let Some(edns) = &mut self.edns {
+            edns.extended_rcode = (code.value() >> 4) as u8;
         }
-        Ok((answers, offset))
     }
 
     pub fn generate_answers(&mut self) {
-        let mut answers = Vec::<Answer>::new();
+        let mut answers = Vec::<ResourceRecord>::new();
         for q in self.questions.iter() {
-            let answer = Answer {
+            let answer = ResourceRecord {
                 name: q.name.clone(),
-                resource_type: q.resource_type.clone(),
                 resource_class: q.resource_class.clone(),
                 ttl: 60,
-                length: 4,
-                data: vec![8, 8, 8, 8],
+                data: RData::A(Ipv4Addr::new(8, 8, 8, 8)),
             };
             answers.push(answer);
             self.header.answer_record_count += 1;
         }
+        self.answers.append(&mut answers);
     }
 
-    pub fn serialize_as_be(self) -> [u8; 512] {
-        let mut bytes: [u8; 512] = [0; 512];
+    pub fn serialize_as_be(&self) -> Result<BytesMut, DnsSerializeError> {
+        self.serialize_with(&SerializeOptions::default())
+    }
 
-        // header
-        let header_bytes: [u8; 12] = self.header.into();
-        bytes[0..=11].copy_from_slice(&header_bytes);
+    pub fn serialize_with(
+        &self,
+        options: &SerializeOptions,
+    ) -> Result<BytesMut, DnsSerializeError> {
+        let mut output = BytesMut::with_capacity(UDP_MESSAGE_SIZE.min(options.max_size));
+        let mut compressor = NameCompressor::new(options.compression);
 
-        let mut start = 12;
+        // header, with counts taken from the sections actually written
+        let mut header = self.header.clone();
+        header.question_count = self.questions.len() as u16;
+        header.answer_record_count = self.answers.len() as u16;
+        header.authority_record_count = self.authority.len() as u16;
+        header.additional_record_count = (self.extra.len() + self.edns.iter().len()) as u16;
+        let header_bytes: [u8; 12] = header.into();
+        output.put_slice(&header_bytes);
 
-        for q in self.questions {
+        // Questions are written in full, but later names may point into them
+        for q in &self.questions {
+            compressor.record_name(&q.name, output.len());
             let q_bytes: Vec<u8> = q.clone().into();
+            output.put_slice(&q_bytes);
+        }
+
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.extra)
+        {
+            record.serialize(&mut compressor, &mut output);
+        }
+        // The OPT pseudo-record always goes last
+        if let Some(edns) = &self.edns {
+            edns.to_record().serialize(&mut compressor, &mut output);
+        }
+
+        if output.len() > options.max_size {
+            return Err(DnsSerializeError::MessageTooLarge {
+                size: output.len(),
+                max: options.max_size,
+            });
+        }
+        Ok(output)
+    }
 
-            let end = start + q_bytes.len();
-            bytes[start..end].copy_from_slice(&q_bytes);
-            start = end;
+    /// Like `serialize_with`, but a message over `options.max_size` loses whole RRsets from
+    /// the end (additional first, then authority, then answers) until it fits. Only losing
+    /// answer or authority records sets the TC bit so the client retries over TCP; additional
+    /// records are optional (RFC 2181 section 9). The OPT record is always kept.
+    pub fn serialize_truncated(
+        &self,
+        options: &SerializeOptions,
+    ) -> Result<BytesMut, DnsSerializeError> {
+        let mut result = self.serialize_with(options);
+        if !matches!(result, Err(DnsSerializeError::MessageTooLarge { .. })) {
+            return result;
         }
 
-        for a in self.answers {
-            let a_bytes: Vec<u8> = a.into();
-            dbg!(String::from_utf8_lossy(&a_bytes));
-            let end = start + a_bytes.len();
-            bytes[start..end].copy_from_slice(&a_bytes);
-            start = end;
+        let mut truncated = self.clone();
+        while matches!(result, Err(DnsSerializeError::MessageTooLarge { .. })) {
+            let dropping_additional = !truncated.extra.is_empty();
+            if !truncated.drop_last_rrset() {
+                break;
+            }
+            if !dropping_additional {
+                truncated.header.set_header_flag(DnsHeaderFlag::Tc(true));
+            }
+            result = truncated.serialize_with(options);
         }
+        result
+    }
 
-        bytes
+    /// Removes the RRset of the last record in the last non-empty section. Returns false once
+    /// only the header, questions and OPT record are left.
+    fn drop_last_rrset(&mut self) -> bool {
+        let section = [&mut self.extra, &mut self.authority, &mut self.answers]
+            .into_iter()
+            .find(|section| !section.is_empty());
+        let Some(section) = section else {
+            return false;
+        };
+        if let Some(last) = section.pop() {
+            section.retain(|record| !record.same_rrset(&last));
+        }
+        true
     }
 }
 
-pub struct Answer {
-    name: DomainName,
-    resource_type: ResourceType,
-    resource_class: ResourceClass,
-    ttl: u32,
-    length: u16,
-    data: Vec<u8>,
+#[derive(Debug, Clone)]
+pub struct ResourceRecord {
+    pub name: DomainName,
+    pub resource_class: ResourceClass,
+    pub ttl: u32,
+    pub data: RData,
 }
 
-impl Answer {
-    // pub fn new() -> Self {
-    //     Answer {
-    //         name: DomainName { content: vec![] },
-    //         resource_type: ResourceType::A,
-    //         resource_class: ResourceClass::IN,
-    //         ttl: 60,
-    //         length: 0,
-    //         data: vec![],
-    //     }
-    // }
-
-    pub fn deserialize(input: &[u8], offset: usize) -> (Self, usize) {
-        // Deserialize each section of the answer
+impl ResourceRecord {
+    pub fn resource_type(&self) -> ResourceType {
+        self.data.resource_type()
+    }
+
+    /// Whether both records belong to the same RRset, i.e. share owner, type and class.
+    pub fn same_rrset(&self, other: &ResourceRecord) -> bool {
+        self.name.eq_ignore_case(&other.name)
+            && self.resource_type() == other.resource_type()
+            && self.resource_class == other.resource_class
+    }
+
+    pub fn deserialize(message: &[u8], offset: usize) -> Result<(Self, usize), DnsParseError> {
+        // Deserialize each section of the record
         // Domain name
-        let (name, name_end_index) = DomainName::deserialize(input, offset);
+        let (name, name_end_index) = DomainName::deserialize(message, offset)?;
 
         // resource type
         let (type_start_index, type_end_index) = (name_end_index + 1, name_end_index + 2);
-        let resource_type_bytes = &input[type_start_index..=type_end_index];
-        let resource_type_u16 = u16::from_be_bytes(resource_type_bytes.try_into().unwrap());
-        let resource_type = ResourceType::try_from(resource_type_u16).unwrap();
+        let resource_type_u16 = read_u16(message, type_start_index)?;
+        let resource_type = ResourceType::from(resource_type_u16);
 
         // resource class
         let (class_start_index, class_end_index) = (type_end_index + 1, type_end_index + 2);
-        let class_type_bytes = &input[class_start_index..=class_end_index];
-        let class_type_u16 = u16::from_be_bytes(class_type_bytes.try_into().unwrap());
-        let resource_class = ResourceClass::try_from(class_type_u16).unwrap();
+        let class_type_u16 = read_u16(message, class_start_index)?;
+        let resource_class = ResourceClass::from(class_type_u16);
 
         // ttl
         let (ttl_start, ttl_end) = (class_end_index + 1, class_end_index + 4);
-        let ttl_bytes = &input[ttl_start..=ttl_end];
-        let ttl = u32::from_be_bytes(ttl_bytes.try_into().unwrap());
+        let ttl = read_u32(message, ttl_start)?;
 
         // length
         let (length_start, length_end) = (ttl_end + 1, ttl_end + 2);
-        let length_bytes = &input[length_start..=length_end];
-        let length = u16::from_be_bytes(length_bytes.try_into().unwrap());
-        dbg!(&name, &resource_type, &resource_class, ttl, length);
+        let length = read_u16(message, length_start)?;
 
         // data
         let data_start = length_end + 1;
+        let data = RData::deserialize(
+            message,
+            data_start,
+            length as usize,
+            &resource_type,
+            &resource_class,
+        )?;
+        // Index of the last byte belonging to this record (the length field if data is empty)
         let data_end = data_start + (length as usize) - 1;
-        let data: Vec<u8> = input[data_start..=data_end].to_vec();
 
-        dbg!(data_start, data_end, &input[data_start..=data_end + 1]);
-        println!("Data is: {}", String::from_utf8_lossy(&data));
-        (
-            Answer {
+        Ok((
+            ResourceRecord {
                 name,
-                resource_type,
                 resource_class,
                 ttl,
-                length,
                 data,
             },
             data_end,
+        ))
+    }
+
+    /// Appends the record to `output`, compressing names through `compressor`. The RDATA
+    /// length is only known once the data has been written, so it is patched in afterwards.
+    pub fn serialize(&self, compressor: &mut NameCompressor, output: &mut BytesMut) {
+        compressor.write_name(&self.name, output);
+        output.put_u16(self.resource_type().value());
+        output.put_u16(self.resource_class.value());
+        output.put_u32(self.ttl);
+
+        let length_index = output.len();
+        output.put_u16(0);
+        self.data.serialize(compressor, output);
+        let length = (output.len() - length_index - 2) as u16;
+        output[length_index..length_index + 2].copy_from_slice(&length.to_be_bytes());
+    }
+}
+
+/// Master file line: `owner TTL class type rdata`.
+impl fmt::Display for ResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.name,
+            self.ttl,
+            self.resource_class,
+            self.resource_type(),
+            self.data
         )
     }
 }
-impl Into<Vec<u8>> for Answer {
-    fn into(self) -> Vec<u8> {
-        let mut output = Vec::<u8>::new();
-        let name_bytes: Vec<u8> = self.name.into();
-        output.extend_from_slice(&name_bytes.as_slice());
-        //self.resource_type.value()
-        // self.resource_class.value()
-        output.extend_from_slice(&u16::to_be_bytes(self.resource_type.value()));
-        output.extend_from_slice(&u16::to_be_bytes(self.resource_class.value()));
-        output.extend_from_slice(&u32::to_be_bytes(self.ttl));
-        output.extend_from_slice(&u16::to_be_bytes(self.length));
-        output.extend_from_slice(&self.data);
-        output
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::dns_question::QueryType;
+
+    fn name(text: &str) -> DomainName {
+        DomainName {
+            content: text
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    /// A response to `www.example.com A` whose names all share suffixes.
+    fn response() -> DnsMessage {
+        let mut response = DnsMessage::query(Question::new(
+            name("www.example.com"),
+            QueryType::Record(ResourceType::A),
+        ));
+        response.answers = vec![
+            record("www.example.com", RData::CNAME(name("web.example.com"))),
+            record("web.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+        ];
+        response.authority = vec![record("example.com", RData::NS(name("ns.example.com")))];
+        response.extra = vec![record(
+            "ns.example.com",
+            RData::A(Ipv4Addr::new(192, 0, 2, 53)),
+        )];
+        response
+    }
+
+    fn all_records(message: &DnsMessage) -> impl Iterator<Item = &ResourceRecord> {
+        message
+            .answers
+            .iter()
+            .chain(&message.authority)
+            .chain(&message.extra)
+    }
+
+    /// Every record in presentation format, which shows its names.
+    fn records(message: &DnsMessage) -> Vec<String> {
+        all_records(message).map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn compressed_messages_parse_back_the_same() {
+        let response = response();
+        let compressed = response.serialize_as_be().unwrap();
+        let uncompressed = response
+            .serialize_with(&SerializeOptions {
+                compression: false,
+                ..SerializeOptions::default()
+            })
+            .unwrap();
+        assert!(compressed.len() < uncompressed.len());
+
+        for bytes in [compressed, uncompressed] {
+            let parsed = DnsMessage::try_from(&bytes[..]).unwrap();
+            assert_eq!(parsed.questions, response.questions);
+            assert_eq!(records(&parsed), records(&response));
+        }
+    }
+
+    #[test]
+    fn writes_no_pointers_without_compression() {
+        let response = response();
+        let bytes = response
+            .serialize_with(&SerializeOptions {
+                compression: false,
+                ..SerializeOptions::default()
+            })
+            .unwrap();
+
+        // Written on their own, names have nothing to point at, so the message is exactly
+        // as long as its parts
+        let question: Vec<u8> = response.questions[0].clone().into();
+        let mut expected = 12 + question.len();
+        for record in all_records(&response) {
+            let mut output = BytesMut::new();
+            record.serialize(&mut NameCompressor::new(false), &mut output);
+            expected += output.len();
+        }
+        assert_eq!(bytes.len(), expected);
+    }
+
+    #[test]
+    fn carries_edns_and_extended_rcodes_in_the_opt_record() {
+        let mut response = response();
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        response.edns = Some(edns);
+        response.set_response_code(ResponseCode::BADVERS);
+
+        let bytes = response.serialize_as_be().unwrap();
+        let parsed = DnsMessage::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed.edns, response.edns);
+        assert_eq!(parsed.get_response_code(), ResponseCode::BADVERS);
+        // The OPT record is taken out of the additional section rather than listed in it
+        assert_eq!(records(&parsed), records(&response));
+    }
+
+    /// A query for `www.example.com A` with ID 0x1234.
+    const QUERY: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+        \x03www\x07example\x03com\x00\x00\x01\x00\x01";
+
+    #[test]
+    fn fails_on_messages_cut_off_anywhere() {
+        assert!(DnsMessage::try_from(QUERY).is_ok());
+        // Short of a header, inside the name, and inside the type and class
+        for length in 0..QUERY.len() {
+            assert!(
+                DnsMessage::try_from(&QUERY[..length]).is_err(),
+                "cut off after {} bytes",
+                length
+            );
+        }
+    }
+
+    fn truncate_to(message: &DnsMessage, max_size: usize) -> DnsMessage {
+        let bytes = message
+            .serialize_truncated(&SerializeOptions {
+                max_size,
+                ..SerializeOptions::default()
+            })
+            .unwrap();
+        assert!(bytes.len() <= max_size);
+        DnsMessage::try_from(&bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn leaves_messages_that_fit_alone() {
+        let response = response();
+        let size = response.serialize_as_be().unwrap().len();
+
+        let truncated = truncate_to(&response, size);
+        assert!(!truncated.header.get_truncation());
+        assert_eq!(records(&truncated), records(&response));
+    }
+
+    #[test]
+    fn drops_additional_records_without_setting_tc() {
+        let response = response();
+        let size = response.serialize_as_be().unwrap().len();
+
+        let truncated = truncate_to(&response, size - 1);
+        assert!(!truncated.header.get_truncation());
+        assert_eq!(truncated.answers.len(), 2);
+        assert_eq!(truncated.authority.len(), 1);
+        assert!(truncated.extra.is_empty());
+    }
+
+    #[test]
+    fn sets_tc_once_answers_are_dropped() {
+        let response = response();
+        let without_authority = DnsMessage {
+            authority: vec![],
+            extra: vec![],
+            ..response.clone()
+        };
+        let size = without_authority.serialize_as_be().unwrap().len();
+
+        let truncated = truncate_to(&response, size - 1);
+        assert!(truncated.header.get_truncation());
+        // Whole RRsets go, from the end of the section
+        assert_eq!(records(&truncated), records(&response)[..1]);
+    }
+
+    #[test]
+    fn keeps_the_opt_record_when_truncating() {
+        let mut response = response();
+        response.edns = Some(Edns::new(1232));
+        let header_and_question = DnsMessage {
+            answers: vec![],
+            authority: vec![],
+            extra: vec![],
+            ..response.clone()
+        };
+        let size = header_and_question.serialize_as_be().unwrap().len();
+
+        let truncated = truncate_to(&response, size);
+        assert!(truncated.header.get_truncation());
+        assert!(all_records(&truncated).next().is_none());
+        assert_eq!(truncated.edns.unwrap().udp_payload_size, 1232);
     }
 }
@@ -1,134 +1,337 @@
-#[derive(Debug, Clone)]
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::{BufMut, BytesMut};
+
+use super::buffer_packets::{read_slice, read_u16, read_u8};
+use super::dns_error::DnsParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DomainName {
-    pub content: Vec<String>,
+    /// Labels as raw bytes, which need not be ASCII or even UTF-8 (RFC 2181 section 11).
+    pub content: Vec<Vec<u8>>,
 }
 
 impl DomainName {
     pub fn new() -> Self {
-        let content = Vec::<String>::new();
+        let content = Vec::<Vec<u8>>::new();
         DomainName { content }
     }
 
-    pub fn deserialize(input: &[u8], offset: usize) -> (Self, usize) {
-        let name_slice = &input[offset..];
-        let mut domain_name = DomainName::new();
+    /// Whether this name is `other` or lies below it, e.g. `www.example.com` is a subdomain
+    /// of `example.com` and of the root.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        self.content.len() >= other.content.len()
+            && self
+                .content
+                .iter()
+                .rev()
+                .zip(other.content.iter().rev())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Names compare case-insensitively for ASCII letters (RFC 4343).
+    pub fn eq_ignore_case(&self, other: &DomainName) -> bool {
+        self.content.len() == other.content.len()
+            && self
+                .content
+                .iter()
+                .zip(&other.content)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// The name lowercased, for keying maps by name: names compare case-insensitively, so
+    /// every spelling of a name has to end up with the same key.
+    pub fn to_lowercase_key(&self) -> String {
+        self.to_string().to_ascii_lowercase()
+    }
+
+    /// Length of the name on the wire without compression: every label with its length
+    /// byte, plus the root label.
+    pub fn encoded_length(&self) -> usize {
+        self.content
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
 
-        if name_slice.len() < 1 {
-            return (domain_name, offset);
+    /// Splits `text` in presentation format into labels, resolving `\.` and `\DDD` escapes
+    /// inside them. Also returns whether it ended in a dot, which is what makes a name in a
+    /// master file absolute. The length of the whole name is left for the caller to check.
+    pub fn parse_labels(text: &str) -> Result<(Self, bool), String> {
+        if text == "." {
+            return Ok((DomainName::new(), true));
         }
 
-        let mut i: usize = 0;
-        let mut ending_index: usize = 0;
-        let mut break_on_not_pointer = false;
-        while i < name_slice.len() {
-            // loop starts on the byte signifying the content length, or a pointer
-            let content_length = name_slice[i] as usize;
-            let is_pointer = (name_slice[i] & 0b11000000) == 0b11000000;
-            if !is_pointer && break_on_not_pointer {
-                // Pointers are not terminated with a null byte (0), which means the current byte
-                // is actually the start of the resource type and the index needs to be set back one.
-                ending_index = i - 1;
-                break;
-            } else if content_length == 0 {
-                // The current length is null byte, which marks the end.
-                ending_index = i;
-                break;
+        let mut raw_labels = vec![String::new()];
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => raw_labels.push(String::new()),
+                '\\' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| format!("name {:?} ends in a lone backslash", text))?;
+                    let label = raw_labels.last_mut().expect("there is always a label");
+                    label.push('\\');
+                    label.push(escaped);
+                }
+                c => raw_labels
+                    .last_mut()
+                    .expect("there is always a label")
+                    .push(c),
             }
+        }
+        // A trailing dot leaves an empty last label
+        let absolute = raw_labels.len() > 1 && raw_labels.last().is_some_and(String::is_empty);
+        if absolute {
+            raw_labels.pop();
+        }
 
-            // Current byte is a pointer or content length. Increment by one to access values.
-            i += 1;
-
-            if is_pointer {
-                // Pointer is two bytes, with the left most two bits set to 11 to indicate compression
-                // Extract the number and unset the compression bits
-                // unfortunately this index offset also needs to account for the header length.
-                // the input slice passed in does NOT contain the header, but the offset is being
-                // made from the start of the header.
-                // let start_index = usize::from_be(input[i].try_into().unwrap());
-                // The pointer specifies the byte to look at using one-based indexing and includes header.
-                // -1 ensures the tag length byte is indexed into.
-                // -13 ensures header is skipped
-                let start_index = (input[i - 1] as usize) - 13;
-                let (mut pointer_domain_name, _) = DomainName::deserialize(input, start_index);
-                // let pointer_content_length = input[start_index] as usize;
-                // let start_of_content = start_index + 1;
-                // let end_of_content = start_of_content + pointer_content_length - 1;
-                // let content_slice = &input[start_of_content..=end_of_content];
-                // let new_content = String::from_utf8_lossy(content_slice).to_string();
-                domain_name.content.append(&mut pointer_domain_name.content);
-                // Currently indexed into second byte of pointer. Skip over it for the next pointer or end.
-                i += 1;
-                // once a pointer is reached, the end of the domain name is marked
-                // by the absence of a pointer, not a null terminating byte.
-                break_on_not_pointer = true;
-            } else {
-                let end_of_content = i + content_length - 1;
-                let content_slice = &name_slice[i..=end_of_content];
-                let new_content = String::from_utf8_lossy(content_slice).to_string();
-                domain_name.content.push(new_content);
-                // Move to next content length byte or pointer
-                i += content_length;
+        let mut content = vec![];
+        for raw_label in raw_labels {
+            let label = unescape(&raw_label)?;
+            if label.is_empty() {
+                return Err(format!("name {:?} has an empty label", text));
             }
+            if label.len() > Self::MAX_LABEL_LENGTH {
+                return Err(format!("label in {:?} is longer than 63 bytes", text));
+            }
+            content.push(label);
         }
+        Ok((DomainName { content }, absolute))
+    }
 
-        (domain_name, offset + ending_index)
+    // Limits on names from RFC 1035 section 2.3.4
+    pub const MAX_LABEL_LENGTH: usize = 63;
+    // Encoded names, including length bytes and the root label, may not exceed 255 bytes
+    pub const MAX_ENCODED_LENGTH: usize = 255;
+    // Every hop has to point further back, so this only bounds pathological but legal chains
+    const MAX_POINTER_HOPS: usize = 32;
+
+    /// Reads the name starting at `offset` of `message`, which must be the whole packet
+    /// (header included) because compression pointers are offsets from its first byte.
+    /// Returns the name and the index of the last byte it occupies at `offset`.
+    pub fn deserialize(message: &[u8], offset: usize) -> Result<(Self, usize), DnsParseError> {
+        let mut domain_name = DomainName::new();
+
+        let mut position = offset;
+        // Once a pointer is followed, the name's own bytes end with that pointer
+        let mut ending_index: Option<usize> = None;
+        let mut hops: usize = 0;
+        let mut encoded_length: usize = 0;
+        loop {
+            // loop starts on the byte signifying the content length, or a pointer
+            let length_byte = read_u8(message, position)?;
+            match length_byte & 0b1100_0000 {
+                0b1100_0000 => {
+                    // Pointer is two bytes, with the left most two bits set to 11 to indicate
+                    // compression. The remaining 14 bits are an offset from the start of the header.
+                    let pointer = (read_u16(message, position)? & 0b0011_1111_1111_1111) as usize;
+                    // Pointers must refer to a prior occurrence, which also rules out loops
+                    if pointer >= position {
+                        return Err(DnsParseError::ForwardPointer(position));
+                    }
+                    hops += 1;
+                    if hops > Self::MAX_POINTER_HOPS {
+                        return Err(DnsParseError::TooManyPointers(offset));
+                    }
+                    ending_index.get_or_insert(position + 1);
+                    position = pointer;
+                }
+                0b0000_0000 => {
+                    let content_length = length_byte as usize;
+                    encoded_length += content_length + 1;
+                    if encoded_length > Self::MAX_ENCODED_LENGTH {
+                        return Err(DnsParseError::NameTooLong(offset));
+                    }
+                    if content_length == 0 {
+                        // The current length is null byte, which marks the end.
+                        let ending_index = ending_index.unwrap_or(position);
+                        return Ok((domain_name, ending_index));
+                    }
+
+                    let content_slice = read_slice(message, position + 1, content_length)?;
+                    domain_name.content.push(content_slice.to_vec());
+                    // Move to next content length byte or pointer
+                    position += content_length + 1;
+                }
+                // 01 and 10 are reserved label types (RFC 6891 retired the extended ones)
+                _ => return Err(DnsParseError::InvalidLabelType(position)),
+            }
+        }
     }
 }
 
-// impl From<&[u8]> for DomainName {
-//     fn from(input: &[u8]) -> DomainName {
-//         let mut domain_name = DomainName::new();
+impl From<DomainName> for Vec<u8> {
+    fn from(name: DomainName) -> Self {
+        let mut encoded = Vec::<u8>::new();
 
-//         if input.len() < 1 {
-//             return domain_name;
-//         }
+        for content in &name.content {
+            let content_length = content.len() as u8;
 
-//         let mut content_length = input[0] as usize;
-//         let mut i: usize = 1;
+            encoded.push(content_length);
+            encoded.extend_from_slice(content);
+        }
 
-//         while i < input.len() {
-//             if input[i] == 0 {
-//                 break;
-//             }
+        encoded.push(0);
 
-//             let end_of_content = std::cmp::min(i + content_length, input.len());
-//             // I thought end_of_content could be len+1 because it's non-inclusive but that's not the case.
-//             // this needs to take min(end_of_content, len)
-//             let new_content = String::from_utf8_lossy(&input[i..end_of_content]).to_string();
-//             domain_name.content.push(new_content);
+        encoded
+    }
+}
 
-//             let old_i = i;
-//             i = i + content_length + 1;
+/// Presentation format: absolute, with a trailing dot, and special characters escaped.
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.content.is_empty() {
+            return write!(f, ".");
+        }
+        for label in &self.content {
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", byte as char)?
+                    }
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+            write!(f, ".")?;
+        }
+        Ok(())
+    }
+}
 
-//             // Set length of next content block
-//             content_length = input[old_i + content_length] as usize;
-//         }
+/// Presentation format as `Display` writes it, with the trailing dot optional.
+impl FromStr for DomainName {
+    type Err = String;
 
-//         domain_name
-//     }
-// }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, _) = DomainName::parse_labels(s)?;
+        if name.encoded_length() > Self::MAX_ENCODED_LENGTH {
+            return Err(format!("name {} is longer than 255 bytes", name));
+        }
+        Ok(name)
+    }
+}
 
-impl Into<Vec<u8>> for DomainName {
-    fn into(self) -> Vec<u8> {
-        let mut encoded = Vec::<u8>::new();
+/// Resolves `\X` and `\DDD` escapes into the bytes they stand for.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some(digit) if digit.is_ascii_digit() => {
+                let mut digits = digit.to_string();
+                for _ in 0..2 {
+                    match chars.next_if(char::is_ascii_digit) {
+                        Some(digit) => digits.push(digit),
+                        None => return Err(format!("escape in {:?} needs three digits", text)),
+                    }
+                }
+                let byte = digits
+                    .parse::<u8>()
+                    .map_err(|_| format!("escape \\{} in {:?} is over 255", digits, text))?;
+                bytes.push(byte);
+            }
+            Some(escaped) => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+            }
+            None => return Err(format!("{:?} ends in a lone backslash", text)),
+        }
+    }
+    Ok(bytes)
+}
 
-        for content in &self.content {
-            let content_length = content.len() as u8;
+/////////////////////////////////////////////////////
+// NAME COMPRESSION
+/////////////////////////////////////////////////////
+/// Suffix table used while serializing a message. Every name written through it is
+/// remembered by offset, so later names sharing a suffix can end in an RFC 1035 pointer
+/// instead of repeating the labels.
+#[derive(Debug)]
+pub struct NameCompressor {
+    enabled: bool,
+    suffixes: HashMap<Vec<u8>, u16>,
+}
 
-            encoded.push(content_length);
-            encoded.extend_from_slice(content.as_bytes());
+impl NameCompressor {
+    // Pointers only have 14 bits for the offset
+    const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+    pub fn new(enabled: bool) -> Self {
+        NameCompressor {
+            enabled,
+            suffixes: HashMap::new(),
+        }
+    }
+
+    /// Appends `name` to `output`, which must contain the message from the first header
+    /// byte onwards, replacing the longest already-written suffix with a pointer.
+    pub fn write_name(&mut self, name: &DomainName, output: &mut BytesMut) {
+        for i in 0..name.content.len() {
+            let suffix = Self::suffix_key(&name.content[i..]);
+            if self.enabled {
+                if let Some(pointer) = self.suffixes.get(&suffix) {
+                    output.put_u16(0b1100_0000_0000_0000 | pointer);
+                    return;
+                }
+            }
+            self.remember(suffix, output.len());
+
+            let label = &name.content[i];
+            output.put_u8(label.len() as u8);
+            output.put_slice(label);
         }
+        output.put_u8(0);
+    }
 
-        encoded.push(0);
+    /// Records a name that was written uncompressed at `offset` so later names can point at it.
+    pub fn record_name(&mut self, name: &DomainName, offset: usize) {
+        let mut label_offset = offset;
+        for i in 0..name.content.len() {
+            self.remember(Self::suffix_key(&name.content[i..]), label_offset);
+            label_offset += name.content[i].len() + 1;
+        }
+    }
 
-        encoded
+    fn remember(&mut self, suffix: Vec<u8>, offset: usize) {
+        if self.enabled && offset <= Self::MAX_POINTER_OFFSET {
+            self.suffixes.entry(suffix).or_insert(offset as u16);
+        }
+    }
+
+    /// Appends `name` in full. Names inside RDATA of types defined after RFC 1035 must not be
+    /// compressed (RFC 3597), but later names may still point at them.
+    pub fn write_name_uncompressed(&mut self, name: &DomainName, output: &mut BytesMut) {
+        self.record_name(name, output.len());
+        let name_bytes: Vec<u8> = name.clone().into();
+        output.put_slice(&name_bytes);
+    }
+
+    // Names compare case-insensitively, so the table does too. Keys keep the length bytes,
+    // as labels may contain dots themselves.
+    fn suffix_key(labels: &[Vec<u8>]) -> Vec<u8> {
+        labels
+            .iter()
+            .flat_map(|label| std::iter::once(label.len() as u8).chain(label.to_ascii_lowercase()))
+            .collect()
     }
 }
 
 /////////////////////////////////////////////////////
 // RESOURCE TYPE
 /////////////////////////////////////////////////////
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ResourceType {
     A,
     NS,
@@ -146,30 +349,50 @@ pub enum ResourceType {
     MINFO,
     MX,
     TXT,
+    AAAA,
+    SRV,
+    NAPTR,
+    DNAME,
+    OPT,
+    SSHFP,
+    TLSA,
+    SVCB,
+    HTTPS,
+    CAA,
+    /// Any type this server has no specific support for (RFC 3597)
+    Unknown(u16),
 }
 
-impl TryFrom<u16> for ResourceType {
-    type Error = String;
-
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<u16> for ResourceType {
+    fn from(value: u16) -> Self {
         match value {
-            1 => Ok(ResourceType::A),
-            2 => Ok(ResourceType::NS),
-            3 => Ok(ResourceType::MD),
-            4 => Ok(ResourceType::MF),
-            5 => Ok(ResourceType::CNAME),
-            6 => Ok(ResourceType::SOA),
-            7 => Ok(ResourceType::MB),
-            8 => Ok(ResourceType::MG),
-            9 => Ok(ResourceType::MR),
-            10 => Ok(ResourceType::NULL),
-            11 => Ok(ResourceType::WKS),
-            12 => Ok(ResourceType::PTR),
-            13 => Ok(ResourceType::HINFO),
-            14 => Ok(ResourceType::MINFO),
-            15 => Ok(ResourceType::MX),
-            16 => Ok(ResourceType::TXT),
-            _ => Err("Cannot map value {value}".to_string()),
+            1 => ResourceType::A,
+            2 => ResourceType::NS,
+            3 => ResourceType::MD,
+            4 => ResourceType::MF,
+            5 => ResourceType::CNAME,
+            6 => ResourceType::SOA,
+            7 => ResourceType::MB,
+            8 => ResourceType::MG,
+            9 => ResourceType::MR,
+            10 => ResourceType::NULL,
+            11 => ResourceType::WKS,
+            12 => ResourceType::PTR,
+            13 => ResourceType::HINFO,
+            14 => ResourceType::MINFO,
+            15 => ResourceType::MX,
+            16 => ResourceType::TXT,
+            28 => ResourceType::AAAA,
+            33 => ResourceType::SRV,
+            35 => ResourceType::NAPTR,
+            39 => ResourceType::DNAME,
+            41 => ResourceType::OPT,
+            44 => ResourceType::SSHFP,
+            52 => ResourceType::TLSA,
+            64 => ResourceType::SVCB,
+            65 => ResourceType::HTTPS,
+            257 => ResourceType::CAA,
+            _ => ResourceType::Unknown(value),
         }
     }
 }
@@ -193,31 +416,118 @@ impl ResourceType {
             ResourceType::MINFO => 14,
             ResourceType::MX => 15,
             ResourceType::TXT => 16,
+            ResourceType::AAAA => 28,
+            ResourceType::SRV => 33,
+            ResourceType::NAPTR => 35,
+            ResourceType::DNAME => 39,
+            ResourceType::OPT => 41,
+            ResourceType::SSHFP => 44,
+            ResourceType::TLSA => 52,
+            ResourceType::SVCB => 64,
+            ResourceType::HTTPS => 65,
+            ResourceType::CAA => 257,
+            ResourceType::Unknown(value) => value,
+        }
+    }
+
+    /// Whether the RDATA layout may differ between classes. Only the RFC 1035 types that
+    /// mean the same in every class are exempt (RFC 3597 section 5), plus OPT, whose class
+    /// field holds the UDP payload size instead.
+    pub fn is_class_specific(&self) -> bool {
+        !matches!(
+            self,
+            ResourceType::NS
+                | ResourceType::MD
+                | ResourceType::MF
+                | ResourceType::CNAME
+                | ResourceType::SOA
+                | ResourceType::MB
+                | ResourceType::MG
+                | ResourceType::MR
+                | ResourceType::NULL
+                | ResourceType::PTR
+                | ResourceType::HINFO
+                | ResourceType::MINFO
+                | ResourceType::MX
+                | ResourceType::TXT
+                | ResourceType::OPT
+        )
+    }
+}
+
+impl fmt::Display for ResourceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceType::Unknown(value) => write!(f, "TYPE{}", value),
+            // The other variant names are the standard mnemonics
+            resource_type => write!(f, "{:?}", resource_type),
         }
     }
 }
 
+/// Mnemonics as used in master files, plus the generic `TYPE123` form (RFC 3597).
+impl FromStr for ResourceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let resource_type = match s.to_ascii_uppercase().as_str() {
+            "A" => ResourceType::A,
+            "NS" => ResourceType::NS,
+            "MD" => ResourceType::MD,
+            "MF" => ResourceType::MF,
+            "CNAME" => ResourceType::CNAME,
+            "SOA" => ResourceType::SOA,
+            "MB" => ResourceType::MB,
+            "MG" => ResourceType::MG,
+            "MR" => ResourceType::MR,
+            "NULL" => ResourceType::NULL,
+            "WKS" => ResourceType::WKS,
+            "PTR" => ResourceType::PTR,
+            "HINFO" => ResourceType::HINFO,
+            "MINFO" => ResourceType::MINFO,
+            "MX" => ResourceType::MX,
+            "TXT" => ResourceType::TXT,
+            "AAAA" => ResourceType::AAAA,
+            "SRV" => ResourceType::SRV,
+            "NAPTR" => ResourceType::NAPTR,
+            "DNAME" => ResourceType::DNAME,
+            "OPT" => ResourceType::OPT,
+            "SSHFP" => ResourceType::SSHFP,
+            "TLSA" => ResourceType::TLSA,
+            "SVCB" => ResourceType::SVCB,
+            "HTTPS" => ResourceType::HTTPS,
+            "CAA" => ResourceType::CAA,
+            upper => match upper.strip_prefix("TYPE").map(str::parse::<u16>) {
+                Some(Ok(value)) => ResourceType::from(value),
+                _ => return Err(format!("unknown record type {:?}", s)),
+            },
+        };
+        Ok(resource_type)
+    }
+}
+
 /////////////////////////////////////////////////////
 // RESOURCE CLASS
 /////////////////////////////////////////////////////
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ResourceClass {
     IN,
     CS,
     CH,
     HS,
     QClassAny,
+    /// Any class this server has no specific support for (RFC 3597)
+    Unknown(u16),
 }
-impl TryFrom<u16> for ResourceClass {
-    type Error = String;
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<u16> for ResourceClass {
+    fn from(value: u16) -> Self {
         match value {
-            1 => Ok(ResourceClass::IN),
-            2 => Ok(ResourceClass::CS),
-            3 => Ok(ResourceClass::CH),
-            4 => Ok(ResourceClass::HS),
-            255 => Ok(ResourceClass::QClassAny),
-            _ => Err("Unable to map value {value}".to_string()),
+            1 => ResourceClass::IN,
+            2 => ResourceClass::CS,
+            3 => ResourceClass::CH,
+            4 => ResourceClass::HS,
+            255 => ResourceClass::QClassAny,
+            _ => ResourceClass::Unknown(value),
         }
     }
 }
@@ -230,6 +540,87 @@ impl ResourceClass {
             ResourceClass::CH => 3,
             ResourceClass::HS => 4,
             ResourceClass::QClassAny => 255,
+            ResourceClass::Unknown(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for ResourceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceClass::QClassAny => write!(f, "ANY"),
+            ResourceClass::Unknown(value) => write!(f, "CLASS{}", value),
+            class => write!(f, "{:?}", class),
+        }
+    }
+}
+
+/// Mnemonics as used in master files, plus the generic `CLASS123` form (RFC 3597).
+impl FromStr for ResourceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let class = match s.to_ascii_uppercase().as_str() {
+            "IN" => ResourceClass::IN,
+            "CS" => ResourceClass::CS,
+            "CH" => ResourceClass::CH,
+            "HS" => ResourceClass::HS,
+            upper => match upper.strip_prefix("CLASS").map(str::parse::<u16>) {
+                Some(Ok(value)) => ResourceClass::from(value),
+                _ => return Err(format!("unknown class {:?}", s)),
+            },
+        };
+        Ok(class)
+    }
+}
+
+/////////////////////////////////////////////////////
+// QUERY TYPE
+/////////////////////////////////////////////////////
+/// QTYPE of a question: any record type, or one of the values that only make sense when
+/// asking (RFC 1035 section 3.2.3, RFC 1995).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum QueryType {
+    Record(ResourceType),
+    IXFR,
+    AXFR,
+    MAILB,
+    MAILA,
+    ANY,
+}
+
+impl From<u16> for QueryType {
+    fn from(value: u16) -> Self {
+        match value {
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            253 => QueryType::MAILB,
+            254 => QueryType::MAILA,
+            255 => QueryType::ANY,
+            _ => QueryType::Record(ResourceType::from(value)),
+        }
+    }
+}
+
+impl QueryType {
+    pub fn value(&self) -> u16 {
+        match self {
+            QueryType::Record(resource_type) => resource_type.value(),
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::MAILB => 253,
+            QueryType::MAILA => 254,
+            QueryType::ANY => 255,
+        }
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryType::Record(resource_type) => write!(f, "{}", resource_type),
+            query_type => write!(f, "{:?}", query_type),
         }
     }
 }
@@ -237,77 +628,315 @@ impl ResourceClass {
 /////////////////////////////////////////////////////
 // QUESTION
 /////////////////////////////////////////////////////
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Question {
     pub name: DomainName,
-    pub resource_type: ResourceType,
+    pub query_type: QueryType,
     pub resource_class: ResourceClass,
 }
 
-// impl From<&[u8]> for Question {
-//     fn from(input: &[u8]) -> Self {
-//         let len = input.len();
-
-//         // The name should be all the bytes up to -4
-//         let name_end_index = len - 4;
-//         let slice = &input[..name_end_index];
-
-//         let name = DomainName::from(slice);
-
-//         // Type should be the bytes -4 to -2
-//         let (type_start_index, type_end_index) = (len - 4, len - 2);
-//         let resource_type_bytes = &input[type_start_index..type_end_index];
-//         let resource_type_u16 = u16::from_be_bytes(resource_type_bytes.try_into().unwrap());
-//         let resource_type = ResourceType::try_from(resource_type_u16).unwrap();
-
-//         // Class should be the last two bytes
-//         let (class_start_index, class_end_index) = (len - 2, len);
-//         let class_type_bytes = &input[class_start_index..class_end_index];
-//         let class_type_u16 = u16::from_be_bytes(class_type_bytes.try_into().unwrap());
-//         let resource_class = ResourceClass::try_from(class_type_u16).unwrap();
-
-//         Question {
-//             name,
-//             resource_type,
-//             resource_class,
-//         }
-//     }
-// }
-
-impl Into<Vec<u8>> for Question {
-    fn into(self) -> Vec<u8> {
+impl From<Question> for Vec<u8> {
+    fn from(question: Question) -> Self {
         let mut output = Vec::<u8>::new();
-        let name_bytes: Vec<u8> = self.name.into();
-        output.extend_from_slice(&name_bytes.as_slice());
+        let name_bytes: Vec<u8> = question.name.into();
+        output.extend_from_slice(name_bytes.as_slice());
 
-        output.extend_from_slice(&u16::to_be_bytes(1));
-        output.extend_from_slice(&u16::to_be_bytes(1));
+        output.extend_from_slice(&u16::to_be_bytes(question.query_type.value()));
+        output.extend_from_slice(&u16::to_be_bytes(question.resource_class.value()));
 
         output
     }
 }
 
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.name, self.resource_class, self.query_type
+        )
+    }
+}
+
 impl Question {
-    pub fn deserialize(input: &[u8], offset: usize) -> (Self, usize) {
-        let (name, name_end_index) = DomainName::deserialize(input, offset);
-        let (type_start_index, type_end_index) = (name_end_index + 1, name_end_index + 2);
-        let resource_type_bytes = &input[type_start_index..=type_end_index];
+    /// A question about `name` in class IN, which is where nearly every question is asked.
+    pub fn new(name: DomainName, query_type: QueryType) -> Self {
+        Question {
+            name,
+            query_type,
+            resource_class: ResourceClass::IN,
+        }
+    }
 
-        let resource_type_u16 = u16::from_be_bytes(resource_type_bytes.try_into().unwrap());
-        let resource_type = ResourceType::try_from(resource_type_u16).unwrap();
+    pub fn deserialize(message: &[u8], offset: usize) -> Result<(Self, usize), DnsParseError> {
+        let (name, name_end_index) = DomainName::deserialize(message, offset)?;
+        let (type_start_index, type_end_index) = (name_end_index + 1, name_end_index + 2);
+        let query_type_u16 = read_u16(message, type_start_index)?;
+        let query_type = QueryType::from(query_type_u16);
 
         let (class_start_index, class_end_index) = (type_end_index + 1, type_end_index + 2);
-        let class_type_bytes = &input[class_start_index..=class_end_index];
-        let class_type_u16 = u16::from_be_bytes(class_type_bytes.try_into().unwrap());
-        let resource_class = ResourceClass::try_from(class_type_u16).unwrap();
+        let class_type_u16 = read_u16(message, class_start_index)?;
+        let resource_class = ResourceClass::from(class_type_u16);
 
-        (
+        Ok((
             Question {
                 name,
-                resource_type,
+                query_type,
                 resource_class,
             },
             class_end_index,
-        )
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(text: &str) -> DomainName {
+        DomainName {
+            content: text
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// Encodes `name` as uncompressed labels, e.g. `www.example.com`.
+    fn encode(name: &str) -> Vec<u8> {
+        let mut encoded = vec![];
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn pointer(offset: usize) -> [u8; 2] {
+        (0b1100_0000_0000_0000 | offset as u16).to_be_bytes()
+    }
+
+    #[test]
+    fn compresses_repeated_suffixes_into_pointers() {
+        let mut compressor = NameCompressor::new(true);
+        let mut output = BytesMut::from(&[0u8; 12][..]);
+        compressor.write_name(&name("www.example.com"), &mut output);
+        let first_end = output.len();
+        compressor.write_name(&name("mail.example.com"), &mut output);
+
+        // The second name keeps its own label and points at "example.com" in the first
+        assert_eq!(&output[first_end..first_end + 5], b"\x04mail");
+        assert_eq!(&output[first_end + 5..], pointer(16));
+    }
+
+    #[test]
+    fn matches_suffixes_regardless_of_case() {
+        let mut compressor = NameCompressor::new(true);
+        let mut output = BytesMut::from(&[0u8; 12][..]);
+        compressor.write_name(&name("www.example.com"), &mut output);
+        let first_end = output.len();
+        compressor.write_name(&name("WWW.Example.COM"), &mut output);
+        assert_eq!(&output[first_end..], pointer(12));
+    }
+
+    #[test]
+    fn writes_names_in_full_when_disabled() {
+        let mut compressor = NameCompressor::new(false);
+        let mut output = BytesMut::new();
+        compressor.record_name(&name("example.com"), 0);
+        compressor.write_name(&name("example.com"), &mut output);
+        compressor.write_name(&name("www.example.com"), &mut output);
+
+        let mut expected = encode("example.com");
+        expected.extend(encode("www.example.com"));
+        assert_eq!(&output[..], expected);
+    }
+
+    #[test]
+    fn reads_an_uncompressed_name() {
+        let mut message = vec![0; 12];
+        message.extend(encode("www.example.com"));
+        let (name, end) = DomainName::deserialize(&message, 12).unwrap();
+        assert_eq!(name.to_string(), "www.example.com.");
+        assert_eq!(end, message.len() - 1);
+    }
+
+    #[test]
+    fn reads_the_root_name() {
+        let (name, end) = DomainName::deserialize(&[0], 0).unwrap();
+        assert!(name.content.is_empty());
+        assert_eq!(end, 0);
+    }
+
+    #[test]
+    fn follows_a_pointer_and_ends_after_it() {
+        let mut message = vec![0; 12];
+        message.extend(encode("example.com"));
+        let start = message.len();
+        message.extend([3, b'w', b'w', b'w']);
+        message.extend(pointer(12));
+        message.extend([0xAA, 0xBB]);
+
+        let (name, end) = DomainName::deserialize(&message, start).unwrap();
+        assert_eq!(name.to_string(), "www.example.com.");
+        assert_eq!(end, start + 5);
+    }
+
+    #[test]
+    fn follows_pointers_to_offsets_above_255() {
+        let mut message = vec![0; 300];
+        message.extend(encode("example.com"));
+        let start = message.len();
+        message.extend(pointer(300));
+
+        let (name, end) = DomainName::deserialize(&message, start).unwrap();
+        assert_eq!(name.to_string(), "example.com.");
+        assert_eq!(end, start + 1);
+    }
+
+    #[test]
+    fn reads_back_compressed_names() {
+        let mut compressor = NameCompressor::new(true);
+        let mut message = BytesMut::from(&[0u8; 12][..]);
+        let names = ["example.com", "www.example.com", "mail.Example.com", "org"];
+        let mut offsets = vec![];
+        for text in names {
+            offsets.push(message.len());
+            compressor.write_name(&name(text), &mut message);
+        }
+
+        for (text, offset) in names.into_iter().zip(offsets) {
+            let (parsed, _) = DomainName::deserialize(&message, offset).unwrap();
+            assert_eq!(
+                parsed.to_string(),
+                format!("{}.", text.to_ascii_lowercase())
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_non_utf8_labels_byte_for_byte() {
+        let mut message = vec![63];
+        message.extend([0xFF; 63]);
+        message.extend([3, b'c', b'o', b'm', 0]);
+        let (name, _) = DomainName::deserialize(&message, 0).unwrap();
+        assert_eq!(name.content[0], vec![0xFF; 63]);
+
+        let encoded: Vec<u8> = name.clone().into();
+        assert_eq!(encoded, message);
+        assert!(name.to_string().starts_with("\\255\\255"));
+    }
+
+    #[test]
+    fn rejects_a_pointer_to_itself() {
+        let mut message = vec![0; 12];
+        message.extend(pointer(12));
+        assert_eq!(
+            DomainName::deserialize(&message, 12),
+            Err(DnsParseError::ForwardPointer(12))
+        );
+    }
+
+    #[test]
+    fn rejects_a_forward_pointer() {
+        let mut message = vec![0; 12];
+        message.extend(pointer(14));
+        message.extend(encode("example.com"));
+        assert_eq!(
+            DomainName::deserialize(&message, 12),
+            Err(DnsParseError::ForwardPointer(12))
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_pointer_hops() {
+        // Every pointer points at the one before it, which is legal but far too long
+        let mut message = vec![0; 12];
+        message.extend(encode("example.com"));
+        let mut previous = 12;
+        for _ in 0..=DomainName::MAX_POINTER_HOPS {
+            let offset = message.len();
+            message.extend(pointer(previous));
+            previous = offset;
+        }
+        assert_eq!(
+            DomainName::deserialize(&message, previous),
+            Err(DnsParseError::TooManyPointers(previous))
+        );
+    }
+
+    #[test]
+    fn rejects_names_over_255_bytes() {
+        let label = "a".repeat(63);
+        let name = [label.as_str(); 4].join(".");
+        let message = encode(&name);
+        assert_eq!(message.len(), 257);
+        assert_eq!(
+            DomainName::deserialize(&message, 0),
+            Err(DnsParseError::NameTooLong(0))
+        );
+
+        // 255 bytes exactly is still fine
+        let name = [
+            label.as_str(),
+            label.as_str(),
+            label.as_str(),
+            &"a".repeat(61),
+        ]
+        .join(".");
+        let message = encode(&name);
+        assert_eq!(message.len(), 255);
+        assert!(DomainName::deserialize(&message, 0).is_ok());
+    }
+
+    #[test]
+    fn rejects_reserved_label_types() {
+        assert_eq!(
+            DomainName::deserialize(&[0b0100_0001, b'a', 0], 0),
+            Err(DnsParseError::InvalidLabelType(0))
+        );
+        assert_eq!(
+            DomainName::deserialize(&[0b1000_0001, b'a', 0], 0),
+            Err(DnsParseError::InvalidLabelType(0))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_names() {
+        assert_eq!(
+            DomainName::deserialize(&[3, b'w', b'w'], 0),
+            Err(DnsParseError::UnexpectedEnd(1))
+        );
+        assert_eq!(
+            DomainName::deserialize(&[3, b'w', b'w', b'w'], 0),
+            Err(DnsParseError::UnexpectedEnd(4))
+        );
+    }
+
+    #[test]
+    fn parses_names_in_presentation_format() {
+        let name: DomainName = "www.Example.com".parse().unwrap();
+        assert_eq!(
+            name.content,
+            [b"www".to_vec(), b"Example".to_vec(), b"com".to_vec()]
+        );
+        assert_eq!("www.Example.com.".parse(), Ok(name.clone()));
+        assert_eq!(name.to_lowercase_key(), "www.example.com.");
+        assert_eq!(".".parse(), Ok(DomainName::new()));
+
+        // Whatever Display escapes comes back as the same bytes
+        let odd = DomainName {
+            content: vec![b"a.b\\ \xff".to_vec(), b"c".to_vec()],
+        };
+        assert_eq!(odd.to_string(), r"a\.b\\\032\255.c.");
+        assert_eq!(odd.to_string().parse(), Ok(odd));
+
+        assert!("a..b".parse::<DomainName>().is_err());
+        assert!("a".repeat(64).parse::<DomainName>().is_err());
+        assert!(vec!["a".repeat(63); 4]
+            .join(".")
+            .parse::<DomainName>()
+            .is_err());
     }
 }
@@ -0,0 +1,919 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use bytes::{BufMut, BytesMut};
+
+use super::buffer_packets::{read_slice, read_u16, read_u32, read_u8};
+use super::dns_edns::EdnsOption;
+use super::dns_error::DnsParseError;
+use super::dns_question::{DomainName, NameCompressor, ResourceClass, ResourceType};
+
+/// Typed RDATA of a resource record, one variant per `ResourceType`.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RData {
+    A(Ipv4Addr),
+    NS(DomainName),
+    MD(DomainName),
+    MF(DomainName),
+    CNAME(DomainName),
+    SOA {
+        mname: DomainName,
+        rname: DomainName,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    MB(DomainName),
+    MG(DomainName),
+    MR(DomainName),
+    NULL(Vec<u8>),
+    WKS {
+        address: Ipv4Addr,
+        protocol: u8,
+        bitmap: Vec<u8>,
+    },
+    PTR(DomainName),
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    MINFO {
+        rmailbx: DomainName,
+        emailbx: DomainName,
+    },
+    MX {
+        preference: u16,
+        exchange: DomainName,
+    },
+    TXT(Vec<Vec<u8>>),
+    AAAA(Ipv6Addr),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName,
+    },
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: DomainName,
+    },
+    /// Redirects every name below the owner to the same name below the target (RFC 6672)
+    DNAME(DomainName),
+    /// Options of the EDNS pseudo-record; the rest of its fields are in `Edns`
+    OPT(Vec<EdnsOption>),
+    SSHFP {
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Vec<u8>,
+    },
+    TLSA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>,
+    },
+    SVCB(ServiceBinding),
+    HTTPS(ServiceBinding),
+    CAA {
+        flags: u8,
+        tag: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// RDATA of a type we don't know, or of a type whose layout depends on a class other
+    /// than IN, relayed byte for byte (RFC 3597)
+    Unknown {
+        resource_type: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// Shared RDATA of SVCB and HTTPS records (RFC 9460).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceBinding {
+    pub priority: u16,
+    pub target: DomainName,
+    pub params: Vec<SvcParam>,
+}
+
+/// A single SvcParam, kept as raw bytes and only interpreted for presentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
+impl SvcParam {
+    fn key_name(&self) -> String {
+        match self.key {
+            0 => "mandatory".to_string(),
+            1 => "alpn".to_string(),
+            2 => "no-default-alpn".to_string(),
+            3 => "port".to_string(),
+            4 => "ipv4hint".to_string(),
+            5 => "ech".to_string(),
+            6 => "ipv6hint".to_string(),
+            key => format!("key{}", key),
+        }
+    }
+}
+
+impl RData {
+    pub fn resource_type(&self) -> ResourceType {
+        match self {
+            RData::A(_) => ResourceType::A,
+            RData::NS(_) => ResourceType::NS,
+            RData::MD(_) => ResourceType::MD,
+            RData::MF(_) => ResourceType::MF,
+            RData::CNAME(_) => ResourceType::CNAME,
+            RData::SOA { .. } => ResourceType::SOA,
+            RData::MB(_) => ResourceType::MB,
+            RData::MG(_) => ResourceType::MG,
+            RData::MR(_) => ResourceType::MR,
+            RData::NULL(_) => ResourceType::NULL,
+            RData::WKS { .. } => ResourceType::WKS,
+            RData::PTR(_) => ResourceType::PTR,
+            RData::HINFO { .. } => ResourceType::HINFO,
+            RData::MINFO { .. } => ResourceType::MINFO,
+            RData::MX { .. } => ResourceType::MX,
+            RData::TXT(_) => ResourceType::TXT,
+            RData::AAAA(_) => ResourceType::AAAA,
+            RData::SRV { .. } => ResourceType::SRV,
+            RData::NAPTR { .. } => ResourceType::NAPTR,
+            RData::DNAME(_) => ResourceType::DNAME,
+            RData::OPT(_) => ResourceType::OPT,
+            RData::SSHFP { .. } => ResourceType::SSHFP,
+            RData::TLSA { .. } => ResourceType::TLSA,
+            RData::SVCB(_) => ResourceType::SVCB,
+            RData::HTTPS(_) => ResourceType::HTTPS,
+            RData::CAA { .. } => ResourceType::CAA,
+            RData::Unknown { resource_type, .. } => ResourceType::from(*resource_type),
+        }
+    }
+
+    /// Parses `length` bytes of RDATA starting at `offset` of the full message. Names inside
+    /// the RDATA may be compressed, so the whole message is needed to follow pointers.
+    /// Outside class IN, types whose layout depends on the class are kept opaque.
+    pub fn deserialize(
+        message: &[u8],
+        offset: usize,
+        length: usize,
+        resource_type: &ResourceType,
+        resource_class: &ResourceClass,
+    ) -> Result<Self, DnsParseError> {
+        let mut reader = RDataReader::new(message, offset, length)?;
+
+        if *resource_class != ResourceClass::IN && resource_type.is_class_specific() {
+            let data = RData::Unknown {
+                resource_type: resource_type.value(),
+                data: reader.rest()?,
+            };
+            reader.finish()?;
+            return Ok(data);
+        }
+
+        let data = match resource_type {
+            ResourceType::A => RData::A(reader.ipv4()?),
+            ResourceType::NS => RData::NS(reader.name()?),
+            ResourceType::MD => RData::MD(reader.name()?),
+            ResourceType::MF => RData::MF(reader.name()?),
+            ResourceType::CNAME => RData::CNAME(reader.name()?),
+            ResourceType::SOA => RData::SOA {
+                mname: reader.name()?,
+                rname: reader.name()?,
+                serial: reader.u32()?,
+                refresh: reader.u32()?,
+                retry: reader.u32()?,
+                expire: reader.u32()?,
+                minimum: reader.u32()?,
+            },
+            ResourceType::MB => RData::MB(reader.name()?),
+            ResourceType::MG => RData::MG(reader.name()?),
+            ResourceType::MR => RData::MR(reader.name()?),
+            ResourceType::NULL => RData::NULL(reader.rest()?),
+            ResourceType::WKS => RData::WKS {
+                address: reader.ipv4()?,
+                protocol: reader.u8()?,
+                bitmap: reader.rest()?,
+            },
+            ResourceType::PTR => RData::PTR(reader.name()?),
+            ResourceType::HINFO => RData::HINFO {
+                cpu: reader.character_string()?,
+                os: reader.character_string()?,
+            },
+            ResourceType::MINFO => RData::MINFO {
+                rmailbx: reader.name()?,
+                emailbx: reader.name()?,
+            },
+            ResourceType::MX => RData::MX {
+                preference: reader.u16()?,
+                exchange: reader.name()?,
+            },
+            ResourceType::TXT => {
+                let mut strings = vec![];
+                while !reader.is_empty() {
+                    strings.push(reader.character_string()?);
+                }
+                RData::TXT(strings)
+            }
+            ResourceType::AAAA => RData::AAAA(reader.ipv6()?),
+            ResourceType::SRV => RData::SRV {
+                priority: reader.u16()?,
+                weight: reader.u16()?,
+                port: reader.u16()?,
+                target: reader.name()?,
+            },
+            ResourceType::NAPTR => RData::NAPTR {
+                order: reader.u16()?,
+                preference: reader.u16()?,
+                flags: reader.character_string()?,
+                services: reader.character_string()?,
+                regexp: reader.character_string()?,
+                replacement: reader.name()?,
+            },
+            ResourceType::DNAME => RData::DNAME(reader.name()?),
+            ResourceType::OPT => {
+                let mut options = vec![];
+                while !reader.is_empty() {
+                    let code = reader.u16()?;
+                    let length = reader.u16()? as usize;
+                    options.push(EdnsOption {
+                        code,
+                        data: reader.bytes(length)?,
+                    });
+                }
+                RData::OPT(options)
+            }
+            ResourceType::SSHFP => RData::SSHFP {
+                algorithm: reader.u8()?,
+                fingerprint_type: reader.u8()?,
+                fingerprint: reader.rest()?,
+            },
+            ResourceType::TLSA => RData::TLSA {
+                usage: reader.u8()?,
+                selector: reader.u8()?,
+                matching_type: reader.u8()?,
+                data: reader.rest()?,
+            },
+            ResourceType::SVCB => RData::SVCB(reader.service_binding()?),
+            ResourceType::HTTPS => RData::HTTPS(reader.service_binding()?),
+            ResourceType::CAA => {
+                let flags = reader.u8()?;
+                let tag = reader.character_string()?;
+                RData::CAA {
+                    flags,
+                    tag,
+                    // The value has no length byte and runs to the end of the RDATA
+                    value: reader.rest()?,
+                }
+            }
+            ResourceType::Unknown(resource_type) => RData::Unknown {
+                resource_type: *resource_type,
+                data: reader.rest()?,
+            },
+        };
+
+        reader.finish()?;
+        Ok(data)
+    }
+
+    /// Appends the RDATA (without its length prefix) to `output`. Only names in types from
+    /// RFC 1035 may be compressed; later types always write them in full.
+    pub fn serialize(&self, compressor: &mut NameCompressor, output: &mut BytesMut) {
+        match self {
+            RData::A(address) => output.put_slice(&address.octets()),
+            RData::NS(name)
+            | RData::MD(name)
+            | RData::MF(name)
+            | RData::CNAME(name)
+            | RData::MB(name)
+            | RData::MG(name)
+            | RData::MR(name)
+            | RData::PTR(name) => compressor.write_name(name, output),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                compressor.write_name(mname, output);
+                compressor.write_name(rname, output);
+                output.put_u32(*serial);
+                output.put_u32(*refresh);
+                output.put_u32(*retry);
+                output.put_u32(*expire);
+                output.put_u32(*minimum);
+            }
+            RData::NULL(data) => output.put_slice(data),
+            RData::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                output.put_slice(&address.octets());
+                output.put_u8(*protocol);
+                output.put_slice(bitmap);
+            }
+            RData::HINFO { cpu, os } => {
+                write_character_string(cpu, output);
+                write_character_string(os, output);
+            }
+            RData::MINFO { rmailbx, emailbx } => {
+                compressor.write_name(rmailbx, output);
+                compressor.write_name(emailbx, output);
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                output.put_u16(*preference);
+                compressor.write_name(exchange, output);
+            }
+            RData::TXT(strings) => {
+                for string in strings {
+                    write_character_string(string, output);
+                }
+            }
+            RData::AAAA(address) => output.put_slice(&address.octets()),
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                output.put_u16(*priority);
+                output.put_u16(*weight);
+                output.put_u16(*port);
+                compressor.write_name_uncompressed(target, output);
+            }
+            RData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                output.put_u16(*order);
+                output.put_u16(*preference);
+                write_character_string(flags, output);
+                write_character_string(services, output);
+                write_character_string(regexp, output);
+                compressor.write_name_uncompressed(replacement, output);
+            }
+            RData::DNAME(target) => compressor.write_name_uncompressed(target, output),
+            RData::OPT(options) => {
+                for option in options {
+                    output.put_u16(option.code);
+                    output.put_u16(option.data.len() as u16);
+                    output.put_slice(&option.data);
+                }
+            }
+            RData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => {
+                output.put_u8(*algorithm);
+                output.put_u8(*fingerprint_type);
+                output.put_slice(fingerprint);
+            }
+            RData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data,
+            } => {
+                output.put_u8(*usage);
+                output.put_u8(*selector);
+                output.put_u8(*matching_type);
+                output.put_slice(data);
+            }
+            RData::SVCB(binding) | RData::HTTPS(binding) => {
+                output.put_u16(binding.priority);
+                compressor.write_name_uncompressed(&binding.target, output);
+                for param in &binding.params {
+                    output.put_u16(param.key);
+                    output.put_u16(param.value.len() as u16);
+                    output.put_slice(&param.value);
+                }
+            }
+            RData::CAA { flags, tag, value } => {
+                output.put_u8(*flags);
+                write_character_string(tag, output);
+                output.put_slice(value);
+            }
+            RData::Unknown { data, .. } => output.put_slice(data),
+        }
+    }
+}
+
+/// Presentation format as used in master files (RFC 1035 section 5 and each type's RFC).
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RData::A(address) => write!(f, "{}", address),
+            RData::NS(name)
+            | RData::MD(name)
+            | RData::MF(name)
+            | RData::CNAME(name)
+            | RData::MB(name)
+            | RData::MG(name)
+            | RData::MR(name)
+            | RData::PTR(name) => write!(f, "{}", name),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            // NULL has no presentation format of its own, so use the generic one
+            RData::NULL(data) | RData::Unknown { data, .. } if data.is_empty() => {
+                write!(f, "\\# 0")
+            }
+            RData::NULL(data) | RData::Unknown { data, .. } => {
+                write!(f, "\\# {} {}", data.len(), Hex(data))
+            }
+            RData::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                write!(f, "{} {}", address, protocol)?;
+                for (index, byte) in bitmap.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0b1000_0000 >> bit) != 0 {
+                            write!(f, " {}", index * 8 + bit)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            RData::HINFO { cpu, os } => {
+                write!(f, "{} {}", CharacterString(cpu), CharacterString(os))
+            }
+            RData::MINFO { rmailbx, emailbx } => write!(f, "{} {}", rmailbx, emailbx),
+            RData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RData::TXT(strings) => {
+                let strings: Vec<String> = strings
+                    .iter()
+                    .map(|string| CharacterString(string).to_string())
+                    .collect();
+                write!(f, "{}", strings.join(" "))
+            }
+            RData::AAAA(address) => write!(f, "{}", address),
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                CharacterString(flags),
+                CharacterString(services),
+                CharacterString(regexp),
+                replacement
+            ),
+            RData::DNAME(target) => write!(f, "{}", target),
+            // OPT never appears in master files; show each option as code:data
+            RData::OPT(options) => {
+                let options: Vec<String> = options
+                    .iter()
+                    .map(|option| format!("{}:{}", option.code, Hex(&option.data)))
+                    .collect();
+                write!(f, "{}", options.join(" "))
+            }
+            RData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => write!(f, "{} {} {}", algorithm, fingerprint_type, Hex(fingerprint)),
+            RData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data,
+            } => write!(f, "{} {} {} {}", usage, selector, matching_type, Hex(data)),
+            RData::SVCB(binding) | RData::HTTPS(binding) => write!(f, "{}", binding),
+            RData::CAA { flags, tag, value } => write!(
+                f,
+                "{} {} {}",
+                flags,
+                String::from_utf8_lossy(tag),
+                CharacterString(value)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ServiceBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.priority, self.target)?;
+        for param in &self.params {
+            write!(f, " {}", param)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = &self.value;
+        match self.key {
+            // mandatory: list of keys
+            0 => {
+                let keys: Vec<String> = value
+                    .chunks_exact(2)
+                    .map(|key| {
+                        SvcParam {
+                            key: u16::from_be_bytes([key[0], key[1]]),
+                            value: vec![],
+                        }
+                        .key_name()
+                    })
+                    .collect();
+                write!(f, "mandatory={}", keys.join(","))
+            }
+            // alpn: list of character-strings
+            1 => {
+                let mut ids = vec![];
+                let mut rest = value.as_slice();
+                while let Some((&length, tail)) = rest.split_first() {
+                    let length = (length as usize).min(tail.len());
+                    ids.push(String::from_utf8_lossy(&tail[..length]).replace(',', "\\,"));
+                    rest = &tail[length..];
+                }
+                write!(f, "alpn=\"{}\"", ids.join(","))
+            }
+            2 => write!(f, "no-default-alpn"),
+            3 if value.len() == 2 => write!(f, "port={}", u16::from_be_bytes([value[0], value[1]])),
+            4 => {
+                let hints: Vec<String> = value
+                    .chunks_exact(4)
+                    .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]).to_string())
+                    .collect();
+                write!(f, "ipv4hint={}", hints.join(","))
+            }
+            5 => write!(f, "ech={}", Base64(value)),
+            6 => {
+                let hints: Vec<String> = value
+                    .chunks_exact(16)
+                    .map(|a| {
+                        let octets: [u8; 16] = a.try_into().unwrap();
+                        Ipv6Addr::from(octets).to_string()
+                    })
+                    .collect();
+                write!(f, "ipv6hint={}", hints.join(","))
+            }
+            _ if value.is_empty() => write!(f, "{}", self.key_name()),
+            _ => write!(f, "{}={}", self.key_name(), CharacterString(value)),
+        }
+    }
+}
+
+/// Quoted <character-string> with `"` and `\` escaped and non-printable bytes as `\DDD`.
+struct CharacterString<'a>(&'a [u8]);
+
+impl fmt::Display for CharacterString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for &byte in self.0 {
+            match byte {
+                b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+                0x20..=0x7E => write!(f, "{}", byte as char)?,
+                _ => write!(f, "\\{:03}", byte)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+struct Base64<'a>(&'a [u8]);
+
+impl fmt::Display for Base64<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        for chunk in self.0.chunks(3) {
+            let bytes = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let index = (group >> (18 - 6 * i)) & 0b11_1111;
+                    write!(f, "{}", ALPHABET[index as usize] as char)?;
+                } else {
+                    write!(f, "=")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// <character-string> is a length byte followed by at most 255 bytes
+fn write_character_string(string: &[u8], output: &mut BytesMut) {
+    let length = string.len().min(255);
+    output.put_u8(length as u8);
+    output.put_slice(&string[..length]);
+}
+
+/// Reads the fields of a single record's RDATA, refusing to go past its declared length.
+struct RDataReader<'a> {
+    message: &'a [u8],
+    start: usize,
+    position: usize,
+    end: usize,
+}
+
+impl<'a> RDataReader<'a> {
+    fn new(message: &'a [u8], offset: usize, length: usize) -> Result<Self, DnsParseError> {
+        // Make sure the whole RDATA is present before reading any of it
+        read_slice(message, offset, length)?;
+        Ok(RDataReader {
+            message,
+            start: offset,
+            position: offset,
+            end: offset + length,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.end
+    }
+
+    fn claim(&mut self, count: usize) -> Result<usize, DnsParseError> {
+        if self.position + count > self.end {
+            return Err(DnsParseError::RDataLength(self.start));
+        }
+        let position = self.position;
+        self.position += count;
+        Ok(position)
+    }
+
+    fn u8(&mut self) -> Result<u8, DnsParseError> {
+        let position = self.claim(1)?;
+        read_u8(self.message, position)
+    }
+
+    fn u16(&mut self) -> Result<u16, DnsParseError> {
+        let position = self.claim(2)?;
+        read_u16(self.message, position)
+    }
+
+    fn u32(&mut self) -> Result<u32, DnsParseError> {
+        let position = self.claim(4)?;
+        read_u32(self.message, position)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<Vec<u8>, DnsParseError> {
+        let position = self.claim(count)?;
+        Ok(read_slice(self.message, position, count)?.to_vec())
+    }
+
+    fn rest(&mut self) -> Result<Vec<u8>, DnsParseError> {
+        self.bytes(self.end - self.position)
+    }
+
+    fn ipv4(&mut self) -> Result<Ipv4Addr, DnsParseError> {
+        let position = self.claim(4)?;
+        let octets = read_slice(self.message, position, 4)?;
+        Ok(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+    }
+
+    fn ipv6(&mut self) -> Result<Ipv6Addr, DnsParseError> {
+        let position = self.claim(16)?;
+        let octets: [u8; 16] = read_slice(self.message, position, 16)?.try_into().unwrap();
+        Ok(Ipv6Addr::from(octets))
+    }
+
+    fn service_binding(&mut self) -> Result<ServiceBinding, DnsParseError> {
+        let priority = self.u16()?;
+        let target = self.name()?;
+        let mut params = vec![];
+        while !self.is_empty() {
+            let key = self.u16()?;
+            let length = self.u16()? as usize;
+            params.push(SvcParam {
+                key,
+                value: self.bytes(length)?,
+            });
+        }
+        Ok(ServiceBinding {
+            priority,
+            target,
+            params,
+        })
+    }
+
+    fn character_string(&mut self) -> Result<Vec<u8>, DnsParseError> {
+        let length = self.u8()? as usize;
+        self.bytes(length)
+    }
+
+    fn name(&mut self) -> Result<DomainName, DnsParseError> {
+        let (name, name_end_index) = DomainName::deserialize(self.message, self.position)?;
+        self.claim(name_end_index + 1 - self.position)?;
+        Ok(name)
+    }
+
+    fn finish(self) -> Result<(), DnsParseError> {
+        if self.position != self.end {
+            return Err(DnsParseError::RDataLength(self.start));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_class_specific_rdata_outside_in_opaque() {
+        // Six bytes are no IPv4 address, but CH A records aren't IN A records
+        let rdata = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        let data = RData::deserialize(&rdata, 0, 6, &ResourceType::A, &ResourceClass::CH);
+        assert_eq!(
+            data,
+            Ok(RData::Unknown {
+                resource_type: 1,
+                data: rdata.to_vec(),
+            })
+        );
+        assert_eq!(data.unwrap().resource_type(), ResourceType::A);
+
+        let data = RData::deserialize(&rdata, 0, 6, &ResourceType::A, &ResourceClass::IN);
+        assert_eq!(data, Err(DnsParseError::RDataLength(0)));
+    }
+
+    #[test]
+    fn parses_class_independent_rdata_in_any_class() {
+        let rdata = [5, b'h', b'e', b'l', b'l', b'o'];
+        let data = RData::deserialize(&rdata, 0, 6, &ResourceType::TXT, &ResourceClass::CH);
+        assert_eq!(data, Ok(RData::TXT(vec![b"hello".to_vec()])));
+    }
+
+    fn name(text: &str) -> DomainName {
+        DomainName {
+            content: text
+                .split('.')
+                .filter(|label| !label.is_empty())
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn round_trip(data: &RData) -> RData {
+        let mut output = BytesMut::new();
+        data.serialize(&mut NameCompressor::new(true), &mut output);
+        let resource_type = data.resource_type();
+        RData::deserialize(&output, 0, output.len(), &resource_type, &ResourceClass::IN).unwrap()
+    }
+
+    fn binding(priority: u16, target: &str, params: &[(u16, &[u8])]) -> ServiceBinding {
+        ServiceBinding {
+            priority,
+            target: name(target),
+            params: params
+                .iter()
+                .map(|&(key, value)| SvcParam {
+                    key,
+                    value: value.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips_and_presents_newer_types() {
+        let cases = [
+            (RData::AAAA("2001:db8::1".parse().unwrap()), "2001:db8::1"),
+            (
+                RData::SRV {
+                    priority: 10,
+                    weight: 60,
+                    port: 5060,
+                    target: name("sip.example.com"),
+                },
+                "10 60 5060 sip.example.com.",
+            ),
+            (
+                RData::NAPTR {
+                    order: 100,
+                    preference: 10,
+                    flags: b"U".to_vec(),
+                    services: b"E2U+sip".to_vec(),
+                    regexp: b"!^.*$!sip:info@example.com!".to_vec(),
+                    replacement: DomainName::new(),
+                },
+                "100 10 \"U\" \"E2U+sip\" \"!^.*$!sip:info@example.com!\" .",
+            ),
+            (
+                RData::SSHFP {
+                    algorithm: 4,
+                    fingerprint_type: 2,
+                    fingerprint: vec![0x12, 0xAB, 0x00],
+                },
+                "4 2 12AB00",
+            ),
+            (
+                RData::TLSA {
+                    usage: 3,
+                    selector: 1,
+                    matching_type: 1,
+                    data: vec![0xDE, 0xAD, 0xBE, 0xEF],
+                },
+                "3 1 1 DEADBEEF",
+            ),
+            (
+                RData::SVCB(binding(
+                    1,
+                    "svc.example.com",
+                    &[(1, b"\x02h2\x02h3"), (3, &[0x20, 0xFB])],
+                )),
+                "1 svc.example.com. alpn=\"h2,h3\" port=8443",
+            ),
+            (
+                RData::HTTPS(binding(0, "example.com", &[])),
+                "0 example.com.",
+            ),
+            (
+                RData::HTTPS(binding(
+                    1,
+                    ".",
+                    &[
+                        (0, &[0, 1]),
+                        (2, &[]),
+                        (4, &[192, 0, 2, 1, 192, 0, 2, 2]),
+                        (5, b"ech"),
+                        (
+                            6,
+                            &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                        ),
+                        (65000, b"x"),
+                    ],
+                )),
+                "1 . mandatory=alpn no-default-alpn ipv4hint=192.0.2.1,192.0.2.2 ech=ZWNo \
+                 ipv6hint=2001:db8::1 key65000=\"x\"",
+            ),
+            (
+                RData::CAA {
+                    flags: 128,
+                    tag: b"issue".to_vec(),
+                    value: b"ca.example.net; account=\"1\"".to_vec(),
+                },
+                "128 issue \"ca.example.net; account=\\\"1\\\"\"",
+            ),
+        ];
+
+        for (data, presentation) in cases {
+            assert_eq!(round_trip(&data), data);
+            assert_eq!(data.to_string(), presentation);
+        }
+    }
+}
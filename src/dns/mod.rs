@@ -1,4 +1,10 @@
+// Protocol definitions mirror the RFCs, so not every type or variant is used by the server.
+#![allow(dead_code)]
+
 pub mod buffer_packets;
+pub mod dns_edns;
+pub mod dns_error;
 pub mod dns_header;
 pub mod dns_message;
 pub mod dns_question;
+pub mod dns_rdata;
@@ -1,111 +1,223 @@
 use dns::{
-    buffer_packets::BytePacketBuffer,
-    dns_header::{DnsHeaderFlag, OperationCode, QueryResponseIndicator, ResponseCode},
-    dns_message::DnsMessage,
+    dns_edns::DEFAULT_EDNS_UDP_SIZE, dns_message::SerializeOptions, dns_question::DomainName,
+};
+use resolver::{
+    cache::{Cache, CacheOptions},
+    forwarder::{Forwarder, ForwarderOptions},
+    recursive::{self, Recursor, RecursorOptions},
+    Resolver,
+};
+use server::{
+    handler::QueryHandler,
+    pool::WorkerPool,
+    tcp::{self, TcpOptions},
+    udp,
 };
-use std::convert::From;
 #[allow(unused_imports)]
 use std::net::UdpSocket;
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use zone::{authority::Zone, catalog::Catalog};
 
 mod dns;
+mod resolver;
+mod server;
+mod zone;
+
+/// Returns the value following `name` on the command line, e.g. `--flag value`.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+/// Returns every value given for `name`, which may be repeated and may hold a comma
+/// separated list, e.g. `--flag a,b --flag c`.
+fn arg_values(name: &str) -> Vec<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.windows(2)
+        .filter(|pair| pair[0] == name)
+        .flat_map(|pair| pair[1].split(','))
+        .map(str::to_string)
+        .collect()
+}
 
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
-    let resolver_address = std::env::args()
-        .nth(2)
-        .unwrap_or("127.0.0.1:2053".to_string());
-
-    // Uncomment this block to pass the first stage
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    // let mut buf = [0; 512];
-
-    let mut packet = BytePacketBuffer::new();
-
-    println!("Resolver: {}", resolver_address);
-
-    loop {
-        match udp_socket.recv_from(&mut packet.buf) {
-            Ok((size, source)) => {
-                println!("Received {} bytes from {}", size, source);
-
-                let mut dns_msg = DnsMessage::from(&packet.buf);
-
-                if resolver_address != "127.0.0.1:2053" {
-                    let resolver_socket = UdpSocket::bind("127.0.0.1:0")
-                        .expect("Failed to bind to address for resolver");
-
-                    // break into one request per question
-                    for i in 0..dns_msg.questions.len() {
-                        // Duplicate dns message, but only send one question at a time.
-                        let mut partial_dns_msg = DnsMessage {
-                            header: dns_msg.header.clone(),
-                            questions: vec![dns_msg.questions[i].clone()],
-                            answers: vec![],
-                            authority: vec![],
-                            extra: vec![],
-                        };
-                        partial_dns_msg.header.question_count = 1;
-                        partial_dns_msg.header.additional_record_count = 0;
-                        partial_dns_msg.header.answer_record_count = 0;
-                        partial_dns_msg.header.authority_record_count = 0;
-
-                        // Send message and parse response
-                        let request = partial_dns_msg.serialize_as_be();
-                        resolver_socket
-                            .send_to(&request, &resolver_address)
-                            .unwrap();
-                        // Parse response
-                        let mut response = BytePacketBuffer::new();
-                        match resolver_socket.recv_from(&mut response.buf) {
-                            Ok((_size, _source)) => {
-                                dbg!(String::from_utf8_lossy(&response.buf));
-                                let mut resolver_dns_msg = DnsMessage::from(&response.buf);
-
-                                if resolver_dns_msg.answers.len() > 0 {
-                                    let answer = resolver_dns_msg.answers.remove(0);
-                                    dns_msg.answers.push(answer);
-                                    dns_msg.header.answer_record_count += 1;
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Error receiving data: {}", e);
-                                break;
-                            }
-                        }
-                    }
-                } else {
-                    dns_msg.generate_answers();
+    // Pointing the resolver at ourselves means answering locally
+    let own_address: SocketAddr = "127.0.0.1:2053".parse().unwrap();
+    let resolver_addresses: Vec<SocketAddr> = arg_values("--resolver")
+        .into_iter()
+        .filter_map(|address| match address.parse() {
+            Ok(address) => Some(address),
+            Err(e) => {
+                eprintln!("Ignoring resolver {:?}: {}", address, e);
+                None
+            }
+        })
+        .filter(|address| *address != own_address)
+        .collect();
+
+    // Uncompressed replies are easier to read in packet dumps
+    let serialize_options = SerializeOptions {
+        compression: !std::env::args().any(|arg| arg == "--no-compression"),
+        ..SerializeOptions::default()
+    };
+
+    // Payload size advertised to EDNS clients and upstreams, which is never below the 512
+    // bytes every DNS message may have
+    let edns_udp_size: u16 = arg_value("--edns-udp-size")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_EDNS_UDP_SIZE)
+        .max(512);
+
+    let default_tcp_options = TcpOptions::default();
+    let tcp_options = TcpOptions {
+        idle_timeout: arg_value("--tcp-idle-timeout")
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(default_tcp_options.idle_timeout),
+        max_connections: arg_value("--tcp-max-connections")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(default_tcp_options.max_connections),
+    };
+
+    let default_forwarder_options = ForwarderOptions::default();
+    let forwarder_options = ForwarderOptions {
+        timeout: arg_value("--upstream-timeout")
+            .and_then(|millis| millis.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default_forwarder_options.timeout),
+        retries: arg_value("--upstream-retries")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(default_forwarder_options.retries),
+        strategy: arg_value("--upstream-strategy")
+            .and_then(|strategy| strategy.parse().ok())
+            .unwrap_or(default_forwarder_options.strategy),
+        max_failures: arg_value("--upstream-max-failures")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(default_forwarder_options.max_failures),
+        probe_interval: arg_value("--upstream-probe-interval")
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(default_forwarder_options.probe_interval),
+        randomize_case: std::env::args().any(|arg| arg == "--upstream-0x20"),
+    };
+
+    let default_cache_options = CacheOptions::default();
+    let cache_options = CacheOptions {
+        max_entries: arg_value("--cache-size")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(default_cache_options.max_entries),
+        min_ttl: arg_value("--cache-min-ttl")
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(default_cache_options.min_ttl),
+        max_ttl: arg_value("--cache-max-ttl")
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(default_cache_options.max_ttl),
+        negative_max_ttl: arg_value("--cache-negative-max-ttl")
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(default_cache_options.negative_max_ttl),
+    };
+
+    let default_recursor_options = RecursorOptions::default();
+    let recursion_port: u16 = arg_value("--recursion-port")
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(default_recursor_options.port);
+    let recursor_options = RecursorOptions {
+        root_hints: match arg_value("--root-hints") {
+            Some(path) => {
+                let hints = std::fs::read_to_string(&path).expect("Failed to read root hints");
+                recursive::parse_root_hints(&hints, recursion_port)
+            }
+            None => default_recursor_options.root_hints,
+        },
+        port: recursion_port,
+        timeout: arg_value("--recursion-timeout")
+            .and_then(|millis| millis.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default_recursor_options.timeout),
+        max_queries: arg_value("--recursion-max-queries")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(default_recursor_options.max_queries),
+        max_depth: arg_value("--recursion-max-depth")
+            .and_then(|depth| depth.parse().ok())
+            .unwrap_or(default_recursor_options.max_depth),
+    };
+
+    // Zones are given as ORIGIN=FILE, e.g. `--zone example.com=example.com.zone`
+    let zones: Vec<Zone> = arg_values("--zone")
+        .into_iter()
+        .map(|zone| {
+            let loaded = zone
+                .split_once('=')
+                .ok_or_else(|| "expected ORIGIN=FILE".to_string())
+                .and_then(|(origin, path)| {
+                    let origin: DomainName = origin.parse()?;
+                    Zone::load(origin, Path::new(path)).map_err(|e| e.to_string())
+                });
+            match loaded {
+                Ok(loaded) => {
+                    println!("Loaded zone {}", loaded.origin);
+                    loaded
                 }
-
-                dns_msg
-                    .header
-                    .set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Response()));
-
-                // For some reason the response code is based on the op code?
-                match dns_msg.header.get_op_code() {
-                    OperationCode::Query() => dns_msg
-                        .header
-                        .set_header_flag(DnsHeaderFlag::RCode(ResponseCode::NoError)),
-                    _ => dns_msg
-                        .header
-                        .set_header_flag(DnsHeaderFlag::RCode(ResponseCode::NotImp)),
+                Err(e) => {
+                    eprintln!("Failed to load zone {:?}: {}", zone, e);
+                    std::process::exit(1);
                 }
+            }
+        })
+        .collect();
+
+    // Workers mostly sit waiting on upstreams, so there are many more of them than cores
+    let worker_count: usize = arg_value("--workers")
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|cores| cores.get() * 4)
+                .unwrap_or(1)
+                .max(32)
+        });
+
+    // Recursion takes over from forwarding when asked for
+    let resolver: Option<Arc<dyn Resolver>> = if std::env::args().any(|arg| arg == "--recursive") {
+        println!(
+            "Resolving recursively from {:?}",
+            recursor_options.root_hints
+        );
+        Some(Arc::new(Recursor::new(recursor_options)))
+    } else if !resolver_addresses.is_empty() {
+        println!("Resolvers: {:?}", resolver_addresses);
+        let forwarder = Arc::new(Forwarder::new(resolver_addresses, forwarder_options));
+        forwarder.spawn_prober();
+        Some(forwarder)
+    } else {
+        None
+    };
+
+    let handler = Arc::new(QueryHandler {
+        resolver,
+        zones: (!zones.is_empty()).then(|| Arc::new(Catalog::new(zones))),
+        // A cache size of 0 turns caching off
+        cache: (cache_options.max_entries > 0).then(|| Arc::new(Cache::new(cache_options))),
+        serialize_options,
+        edns_udp_size,
+    });
 
-                println!("Flags after modification: {:016b}", dns_msg.header.flags);
-                let response: [u8; 512] = dns_msg.serialize_as_be();
+    // Uncomment this block to pass the first stage
+    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
+    let tcp_listener = TcpListener::bind("127.0.0.1:2053").expect("Failed to bind to address");
 
-                println!("Flags after encoding: {:#?}", &response[..=11]);
+    let tcp_handler = Arc::clone(&handler);
+    thread::spawn(move || tcp::serve(tcp_listener, tcp_handler, tcp_options));
 
-                udp_socket
-                    .send_to(&response, source)
-                    .expect("Failed to send response");
-            }
-            Err(e) => {
-                eprintln!("Error receiving data: {}", e);
-                break;
-            }
-        }
-    }
+    let pool = WorkerPool::new(worker_count, worker_count * 64);
+    udp::serve(udp_socket, handler, &pool);
 }
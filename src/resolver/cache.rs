@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::dns::{
+    dns_header::{DnsHeaderFlag, ResponseCode},
+    dns_message::{DnsMessage, ResourceRecord},
+    dns_question::{QueryType, Question, ResourceClass, ResourceType},
+    dns_rdata::RData,
+};
+
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    /// Responses kept at most; the least recently used one makes room for a new one.
+    pub max_entries: usize,
+    /// Record TTLs are raised to at least this many seconds before caching.
+    pub min_ttl: u32,
+    /// Record TTLs are lowered to at most this many seconds before caching.
+    pub max_ttl: u32,
+    /// Upper limit for how long NXDOMAIN and NODATA answers are kept.
+    pub negative_max_ttl: u32,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            max_entries: 10_000,
+            min_ttl: 0,
+            // One day, as recommended by RFC 8767
+            max_ttl: 86_400,
+            // Three hours, the upper end suggested by RFC 2308 section 5
+            negative_max_ttl: 10_800,
+        }
+    }
+}
+
+/// Names are compared case-insensitively, so the key holds the lowercased name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    name: String,
+    /// `None` for an entry covering every type at the name, i.e. an NXDOMAIN.
+    query_type: Option<QueryType>,
+    resource_class: ResourceClass,
+}
+
+impl CacheKey {
+    fn new(question: &Question, query_type: Option<QueryType>) -> Self {
+        CacheKey {
+            name: question.name.to_lowercase_key(),
+            query_type,
+            resource_class: question.resource_class.clone(),
+        }
+    }
+}
+
+/// How a response can be cached, following the terms of RFC 2308 section 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnswerKind {
+    /// Records answering the question.
+    Positive,
+    /// The name exists but has no records of the type asked for.
+    NoData,
+    /// The name doesn't exist at all.
+    NameError,
+}
+
+impl AnswerKind {
+    /// Referrals and failures get `None`: they don't say anything lasting about the name.
+    fn of(response: &DnsMessage) -> Option<Self> {
+        let has_soa = response
+            .authority
+            .iter()
+            .any(|record| record.resource_type() == ResourceType::SOA);
+        match response.get_response_code() {
+            ResponseCode::NXDomain => Some(AnswerKind::NameError),
+            ResponseCode::NoError if !response.answers.is_empty() => Some(AnswerKind::Positive),
+            ResponseCode::NoError if has_soa => Some(AnswerKind::NoData),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// The upstream response with every TTL clamped as it was when stored.
+    response: DnsMessage,
+    stored_at: Instant,
+    /// Lifetime of the entry: the lowest TTL of any record in it.
+    ttl: Duration,
+    /// Position in `CacheInner::recently_used`.
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys ordered from least to most recently used.
+    recently_used: BTreeMap<u64, CacheKey>,
+    /// Incremented on every use, so it orders `recently_used`.
+    clock: u64,
+}
+
+/// Upstream responses kept for as long as their records' TTLs allow, keyed on the question
+/// they answer. Negative answers are kept too, for as long as their SOA record allows.
+#[derive(Debug)]
+pub struct Cache {
+    pub options: CacheOptions,
+    inner: Mutex<CacheInner>,
+}
+
+impl Cache {
+    pub fn new(options: CacheOptions) -> Self {
+        Cache {
+            options,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// Returns the cached response to `question` with every TTL reduced by the time spent in
+    /// the cache, or `None` if there is none or it has expired. A cached NXDOMAIN for the name
+    /// answers questions of any type.
+    pub fn get(&self, question: &Question) -> Option<DnsMessage> {
+        let mut inner = self.inner.lock().unwrap();
+        let (mut response, age) = Self::lookup(
+            &mut inner,
+            CacheKey::new(question, Some(question.query_type.clone())),
+        )
+        .or_else(|| Self::lookup(&mut inner, CacheKey::new(question, None)))?;
+
+        let age = age.as_secs() as u32;
+        for record in Self::records_mut(&mut response) {
+            record.ttl -= age.min(record.ttl);
+        }
+        // Data served from the cache never counts as authoritative
+        response.header.set_header_flag(DnsHeaderFlag::Aa(false));
+        Some(response)
+    }
+
+    /// Stores `response` as the answer to `question` if it is worth keeping: a complete
+    /// answer, or a negative one carrying the zone's SOA record, whose records all live
+    /// longer than zero seconds after clamping.
+    pub fn insert(&self, question: &Question, response: &DnsMessage) {
+        if self.options.max_entries == 0 || response.header.get_truncation() {
+            return;
+        }
+        let Some(kind) = AnswerKind::of(response) else {
+            return;
+        };
+
+        let mut response = response.clone();
+        response.edns = None;
+        self.clamp_ttls(&mut response);
+        let ttl = Self::records_mut(&mut response)
+            .map(|record| record.ttl)
+            .min()
+            .unwrap_or(0);
+        if ttl == 0 {
+            return;
+        }
+
+        // An NXDOMAIN holds for every type, unless it is about the end of a CNAME chain
+        let key = match kind {
+            AnswerKind::NameError if response.answers.is_empty() => CacheKey::new(question, None),
+            _ => CacheKey::new(question, Some(question.query_type.clone())),
+        };
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = CacheEntry {
+            response,
+            stored_at: Instant::now(),
+            ttl: Duration::from_secs(ttl as u64),
+            last_used: clock,
+        };
+        if let Some(replaced) = inner.entries.insert(key.clone(), entry) {
+            inner.recently_used.remove(&replaced.last_used);
+        }
+        inner.recently_used.insert(clock, key);
+
+        while inner.entries.len() > self.options.max_entries {
+            let Some((_, oldest)) = inner.recently_used.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+    }
+
+    /// Applies the min/max TTL limits to every record, so a fresh answer from upstream shows
+    /// the same TTLs it will have in the cache. In a negative answer the SOA record's TTL
+    /// becomes the time the answer may be cached: the lower of its own TTL and the SOA
+    /// MINIMUM field (RFC 2308 section 5).
+    pub fn clamp_ttls(&self, response: &mut DnsMessage) {
+        let negative = matches!(
+            AnswerKind::of(response),
+            Some(AnswerKind::NoData) | Some(AnswerKind::NameError)
+        );
+        for record in Self::records_mut(response) {
+            // Not `clamp`, which panics when the limits cross; the maximum wins then
+            record.ttl = record
+                .ttl
+                .max(self.options.min_ttl)
+                .min(self.options.max_ttl);
+        }
+        if !negative {
+            return;
+        }
+        for record in &mut response.authority {
+            if let RData::SOA { minimum, .. } = record.data {
+                record.ttl = record
+                    .ttl
+                    .min(minimum)
+                    .max(self.options.min_ttl)
+                    .min(self.options.negative_max_ttl);
+            }
+        }
+    }
+
+    /// Finds the live entry for `key`, marking it as just used, and returns a copy of its
+    /// response along with its age. Expired entries are removed on the way.
+    fn lookup(inner: &mut CacheInner, key: CacheKey) -> Option<(DnsMessage, Duration)> {
+        let entry = inner.entries.get(&key)?;
+        let age = entry.stored_at.elapsed();
+        if age >= entry.ttl {
+            let last_used = entry.last_used;
+            inner.entries.remove(&key);
+            inner.recently_used.remove(&last_used);
+            return None;
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(&key)?;
+        let previous_use = std::mem::replace(&mut entry.last_used, clock);
+        let response = entry.response.clone();
+        inner.recently_used.remove(&previous_use);
+        inner.recently_used.insert(clock, key);
+        Some((response, age))
+    }
+
+    fn records_mut(response: &mut DnsMessage) -> impl Iterator<Item = &mut ResourceRecord> {
+        response
+            .answers
+            .iter_mut()
+            .chain(response.authority.iter_mut())
+            .chain(response.extra.iter_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::dns::dns_question::DomainName;
+
+    fn name(name: &str) -> DomainName {
+        DomainName {
+            content: name
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn question(owner: &str, resource_type: ResourceType) -> Question {
+        Question::new(name(owner), QueryType::Record(resource_type))
+    }
+
+    fn address(owner: &str, ttl: u32) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            resource_class: ResourceClass::IN,
+            ttl,
+            data: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        }
+    }
+
+    /// Makes every entry look `seconds` older than it is.
+    fn age(cache: &Cache, seconds: u64) {
+        for entry in cache.inner.lock().unwrap().entries.values_mut() {
+            entry.stored_at -= Duration::from_secs(seconds);
+        }
+    }
+
+    fn response(answers: Vec<ResourceRecord>, authority: Vec<ResourceRecord>) -> DnsMessage {
+        let mut response = DnsMessage::query(question("www.example.com", ResourceType::A));
+        response.answers = answers;
+        response.authority = authority;
+        response
+    }
+
+    #[test]
+    fn lets_max_ttl_win_when_the_limits_cross() {
+        let cache = Cache::new(CacheOptions {
+            min_ttl: 1000,
+            max_ttl: 100,
+            ..CacheOptions::default()
+        });
+        let mut response = response(vec![address("www.example.com", 300)], vec![]);
+
+        cache.clamp_ttls(&mut response);
+        assert_eq!(response.answers[0].ttl, 100);
+    }
+
+    #[test]
+    fn lets_negative_max_ttl_win_over_min_ttl() {
+        let cache = Cache::new(CacheOptions {
+            min_ttl: 20_000,
+            ..CacheOptions::default()
+        });
+        let mut response = response(vec![], vec![soa(3600, 300)]);
+        response.set_response_code(ResponseCode::NXDomain);
+
+        cache.clamp_ttls(&mut response);
+        assert_eq!(response.authority[0].ttl, 10_800);
+    }
+
+    #[test]
+    fn counts_down_ttls_while_cached() {
+        let cache = Cache::new(CacheOptions::default());
+        let www = question("www.example.com", ResourceType::A);
+        let mut answer = response(vec![address("www.example.com", 300)], vec![]);
+        answer.header.set_header_flag(DnsHeaderFlag::Aa(true));
+        cache.insert(&www, &answer);
+
+        age(&cache, 10);
+        let cached = cache.get(&www).unwrap();
+        assert_eq!(cached.answers[0].ttl, 290);
+        assert!(!cached.header.get_authoritative_answer());
+    }
+
+    #[test]
+    fn drops_expired_entries() {
+        let cache = Cache::new(CacheOptions::default());
+        let www = question("www.example.com", ResourceType::A);
+        cache.insert(&www, &response(vec![address("www.example.com", 5)], vec![]));
+
+        age(&cache, 5);
+        assert!(cache.get(&www).is_none());
+        assert!(cache.inner.lock().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = Cache::new(CacheOptions {
+            max_entries: 2,
+            ..CacheOptions::default()
+        });
+        let [a, b, c] = ["a.example.com", "b.example.com", "c.example.com"]
+            .map(|name| (question(name, ResourceType::A), address(name, 300)));
+        cache.insert(&a.0, &response(vec![a.1], vec![]));
+        cache.insert(&b.0, &response(vec![b.1], vec![]));
+        // Using a makes b the least recently used
+        assert!(cache.get(&a.0).is_some());
+        cache.insert(&c.0, &response(vec![c.1], vec![]));
+
+        assert!(cache.get(&a.0).is_some());
+        assert!(cache.get(&b.0).is_none());
+        assert!(cache.get(&c.0).is_some());
+    }
+
+    #[test]
+    fn keeps_no_truncated_referral_or_failed_responses() {
+        let cache = Cache::new(CacheOptions::default());
+        let www = question("www.example.com", ResourceType::A);
+
+        let mut truncated = response(vec![address("www.example.com", 300)], vec![]);
+        truncated.header.set_header_flag(DnsHeaderFlag::Tc(true));
+        let referral = response(
+            vec![],
+            vec![ResourceRecord {
+                name: name("example.com"),
+                resource_class: ResourceClass::IN,
+                ttl: 300,
+                data: RData::NS(name("ns.example.com")),
+            }],
+        );
+        let mut failure = response(vec![], vec![]);
+        failure.set_response_code(ResponseCode::ServFail);
+
+        for response in [truncated, referral, failure] {
+            cache.insert(&www, &response);
+            assert!(cache.get(&www).is_none());
+        }
+    }
+
+    /// The SOA of example.com as it comes with negative answers.
+    fn soa(ttl: u32, minimum: u32) -> ResourceRecord {
+        ResourceRecord {
+            name: name("example.com"),
+            resource_class: ResourceClass::IN,
+            ttl,
+            data: RData::SOA {
+                mname: name("ns.example.com"),
+                rname: name("hostmaster.example.com"),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86_400,
+                minimum,
+            },
+        }
+    }
+
+    fn name_error(answers: Vec<ResourceRecord>) -> DnsMessage {
+        let mut response = response(answers, vec![soa(3600, 300)]);
+        response.set_response_code(ResponseCode::NXDomain);
+        response
+    }
+
+    #[test]
+    fn answers_every_type_from_a_cached_nxdomain() {
+        let cache = Cache::new(CacheOptions::default());
+        cache.insert(
+            &question("www.example.com", ResourceType::A),
+            &name_error(vec![]),
+        );
+
+        for resource_type in [ResourceType::A, ResourceType::AAAA, ResourceType::MX] {
+            let cached = cache.get(&question("WWW.example.com", resource_type));
+            assert_eq!(cached.unwrap().get_response_code(), ResponseCode::NXDomain);
+        }
+        assert!(cache
+            .get(&question("mail.example.com", ResourceType::A))
+            .is_none());
+    }
+
+    #[test]
+    fn caches_nodata_per_type() {
+        let cache = Cache::new(CacheOptions::default());
+        let www_aaaa = question("www.example.com", ResourceType::AAAA);
+        cache.insert(&www_aaaa, &response(vec![], vec![soa(3600, 300)]));
+
+        let cached = cache.get(&www_aaaa).unwrap();
+        assert_eq!(cached.get_response_code(), ResponseCode::NoError);
+        assert!(cached.answers.is_empty());
+        assert!(cache
+            .get(&question("www.example.com", ResourceType::A))
+            .is_none());
+    }
+
+    #[test]
+    fn keeps_negative_answers_no_longer_than_the_soa_minimum() {
+        let cache = Cache::new(CacheOptions::default());
+        let www = question("www.example.com", ResourceType::A);
+        cache.insert(&www, &name_error(vec![]));
+
+        assert_eq!(cache.get(&www).unwrap().authority[0].ttl, 300);
+        age(&cache, 300);
+        assert!(cache.get(&www).is_none());
+    }
+
+    #[test]
+    fn keys_nxdomain_after_a_cname_by_type() {
+        let cache = Cache::new(CacheOptions::default());
+        let www = question("www.example.com", ResourceType::A);
+        // It is the CNAME target that doesn't exist, not www.example.com
+        let alias = ResourceRecord {
+            name: name("www.example.com"),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data: RData::CNAME(name("gone.example.com")),
+        };
+        cache.insert(&www, &name_error(vec![alias]));
+
+        let cached = cache.get(&www).unwrap();
+        assert_eq!(cached.get_response_code(), ResponseCode::NXDomain);
+        assert_eq!(cached.answers.len(), 1);
+        assert!(cache
+            .get(&question("www.example.com", ResourceType::AAAA))
+            .is_none());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::dns::{
+    dns_header::{DnsHeaderFlag, ResponseCode},
+    dns_message::DnsMessage,
+    dns_question::{DomainName, QueryType, Question, ResourceType},
+    dns_rdata::RData,
+};
+
+use super::resolver_error::ResolverError;
+
+/// CNAME and DNAME links followed for one question before giving up.
+pub const MAX_CHAIN_LENGTH: usize = 8;
+
+/// Answers `question` through `lookup`, following CNAME and DNAME records (RFC 1034
+/// section 3.6.2, RFC 6672) until the records asked for turn up. Links already present in a
+/// response are followed within it; where the chain leaves the response, the new name is
+/// looked up again. All answers are returned together, while the RCODE and the other
+/// sections come from the last response, which is about the end of the chain.
+pub fn follow_chain(
+    question: &Question,
+    max_links: usize,
+    mut lookup: impl FnMut(&Question) -> Result<DnsMessage, ResolverError>,
+) -> Result<DnsMessage, ResolverError> {
+    // Chains only lead somewhere for a single type other than CNAME itself
+    let wanted = match &question.query_type {
+        QueryType::Record(ResourceType::CNAME) => return lookup(question),
+        QueryType::Record(resource_type) => resource_type.clone(),
+        _ => return lookup(question),
+    };
+
+    let mut name = question.name.clone();
+    let mut seen = vec![name.clone()];
+    let mut answers = vec![];
+    let mut authoritative = true;
+    loop {
+        let current = Question {
+            name: name.clone(),
+            ..question.clone()
+        };
+        let mut response = lookup(&current)?;
+        authoritative &= response.header.get_authoritative_answer();
+
+        let (end, complete) = walk_chain(&response, &name, &wanted, &mut seen, max_links)?;
+        answers.append(&mut response.answers);
+
+        // A negative answer with a SOA is about the end of the chain already
+        let has_soa = response
+            .authority
+            .iter()
+            .any(|record| record.resource_type() == ResourceType::SOA);
+        let stuck = end.eq_ignore_case(&name);
+        if complete || stuck || has_soa || response.get_response_code() != ResponseCode::NoError {
+            response.header.answer_record_count = answers.len() as u16;
+            response.answers = answers;
+            response
+                .header
+                .set_header_flag(DnsHeaderFlag::Aa(authoritative));
+            return Ok(response);
+        }
+        println!("Following chain from {} to {}", name, end);
+        name = end;
+    }
+}
+
+/// Follows the links in `response` from `name` on. Returns the name the chain ends at and
+/// whether the response holds `wanted` records for it.
+fn walk_chain(
+    response: &DnsMessage,
+    name: &DomainName,
+    wanted: &ResourceType,
+    seen: &mut Vec<DomainName>,
+    max_links: usize,
+) -> Result<(DomainName, bool), ResolverError> {
+    let mut name = name.clone();
+    loop {
+        let complete = response
+            .answers
+            .iter()
+            .any(|record| record.name.eq_ignore_case(&name) && record.resource_type() == *wanted);
+        if complete {
+            return Ok((name, true));
+        }
+        let Some(target) = next_link(response, &name) else {
+            return Ok((name, false));
+        };
+
+        if seen
+            .iter()
+            .any(|seen_name| seen_name.eq_ignore_case(&target))
+        {
+            return Err(ResolverError::ChainLoop(target.to_string()));
+        }
+        if seen.len() > max_links {
+            return Err(ResolverError::ChainTooLong(max_links));
+        }
+        seen.push(target.clone());
+        name = target;
+    }
+}
+
+/// Where the answers in `response` send `name`: a DNAME above it substitutes its own name
+/// for the owner's, and otherwise a CNAME at the name points elsewhere. The DNAME goes
+/// first since the CNAME next to it is only synthesized from it.
+fn next_link(response: &DnsMessage, name: &DomainName) -> Option<DomainName> {
+    let dname = response
+        .answers
+        .iter()
+        .find_map(|record| match &record.data {
+            RData::DNAME(target)
+                if name.content.len() > record.name.content.len()
+                    && name.is_subdomain_of(&record.name) =>
+            {
+                let prefix = name.content.len() - record.name.content.len();
+                let mut content = name.content[..prefix].to_vec();
+                content.extend(target.content.iter().cloned());
+                Some(DomainName { content })
+            }
+            _ => None,
+        });
+    dname.or_else(|| {
+        response
+            .answers
+            .iter()
+            .find_map(|record| match &record.data {
+                RData::CNAME(target) if record.name.eq_ignore_case(name) => Some(target.clone()),
+                _ => None,
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::Ipv4Addr};
+
+    use super::*;
+    use crate::dns::{dns_message::ResourceRecord, dns_question::ResourceClass};
+
+    fn name(name: &str) -> DomainName {
+        DomainName {
+            content: name
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    fn cname(owner: &str, target: &str) -> ResourceRecord {
+        record(owner, RData::CNAME(name(target)))
+    }
+
+    fn address(owner: &str) -> ResourceRecord {
+        record(owner, RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+    }
+
+    /// Follows the chain from `owner` where each lookup answers with the records listed for
+    /// the name asked about, and NXDOMAIN for names not listed. Also returns the names asked.
+    fn chase(
+        owner: &str,
+        responses: &[(&str, Vec<ResourceRecord>)],
+        max_links: usize,
+    ) -> (Result<DnsMessage, ResolverError>, Vec<String>) {
+        let responses: HashMap<String, Vec<ResourceRecord>> = responses
+            .iter()
+            .map(|(name, answers)| (name.to_string(), answers.clone()))
+            .collect();
+        let mut asked = vec![];
+        let question = Question::new(name(owner), QueryType::Record(ResourceType::A));
+        let result = follow_chain(&question, max_links, |question| {
+            let name = question.name.to_lowercase_key();
+            let mut response = DnsMessage::query(question.clone());
+            match responses.get(name.trim_end_matches('.')) {
+                Some(answers) => response.answers = answers.clone(),
+                None => response.set_response_code(ResponseCode::NXDomain),
+            }
+            asked.push(name);
+            Ok(response)
+        });
+        (result, asked)
+    }
+
+    fn link_names(count: usize) -> Vec<String> {
+        (0..=count).map(|i| format!("n{}.example.com", i)).collect()
+    }
+
+    /// Responses for a CNAME chain through `names`, one link each, ending at an address.
+    fn chain(names: &[String]) -> Vec<(&str, Vec<ResourceRecord>)> {
+        let mut responses: Vec<(&str, Vec<ResourceRecord>)> = names
+            .windows(2)
+            .map(|pair| (pair[0].as_str(), vec![cname(&pair[0], &pair[1])]))
+            .collect();
+        let last = names.last().unwrap();
+        responses.push((last, vec![address(last)]));
+        responses
+    }
+
+    #[test]
+    fn looks_up_each_link_outside_the_response() {
+        let names = link_names(2);
+        let (result, asked) = chase(&names[0], &chain(&names), MAX_CHAIN_LENGTH);
+
+        let response = result.unwrap();
+        assert_eq!(response.answers.len(), 3);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert_eq!(
+            asked,
+            ["n0.example.com.", "n1.example.com.", "n2.example.com."]
+        );
+    }
+
+    #[test]
+    fn follows_links_within_one_response() {
+        let responses = [(
+            "www.example.com",
+            vec![
+                cname("www.example.com", "web.example.com"),
+                address("web.example.com"),
+            ],
+        )];
+        let (result, asked) = chase("www.example.com", &responses, MAX_CHAIN_LENGTH);
+
+        assert_eq!(result.unwrap().answers.len(), 2);
+        assert_eq!(asked.len(), 1);
+    }
+
+    #[test]
+    fn fails_on_loops() {
+        let responses = [
+            (
+                "a.example.com",
+                vec![cname("a.example.com", "b.example.com")],
+            ),
+            (
+                "b.example.com",
+                vec![cname("b.example.com", "A.example.com")],
+            ),
+        ];
+        let (result, _) = chase("a.example.com", &responses, MAX_CHAIN_LENGTH);
+        assert!(matches!(result, Err(ResolverError::ChainLoop(_))));
+
+        let responses = [(
+            "self.example.com",
+            vec![cname("self.example.com", "self.example.com")],
+        )];
+        let (result, _) = chase("self.example.com", &responses, MAX_CHAIN_LENGTH);
+        assert!(matches!(result, Err(ResolverError::ChainLoop(_))));
+    }
+
+    #[test]
+    fn gives_up_after_max_chain_length_links() {
+        let names = link_names(MAX_CHAIN_LENGTH);
+        let (result, _) = chase(&names[0], &chain(&names), MAX_CHAIN_LENGTH);
+        assert_eq!(result.unwrap().answers.len(), MAX_CHAIN_LENGTH + 1);
+
+        let names = link_names(MAX_CHAIN_LENGTH + 1);
+        let (result, _) = chase(&names[0], &chain(&names), MAX_CHAIN_LENGTH);
+        assert!(matches!(
+            result,
+            Err(ResolverError::ChainTooLong(MAX_CHAIN_LENGTH))
+        ));
+    }
+
+    #[test]
+    fn substitutes_dname_targets() {
+        let responses = [
+            (
+                "www.sub.example.com",
+                vec![record("example.com", RData::DNAME(name("example.net")))],
+            ),
+            ("www.sub.example.net", vec![address("www.sub.example.net")]),
+        ];
+        let (result, asked) = chase("www.sub.example.com", &responses, MAX_CHAIN_LENGTH);
+
+        assert_eq!(result.unwrap().answers.len(), 2);
+        assert_eq!(asked, ["www.sub.example.com.", "www.sub.example.net."]);
+    }
+
+    #[test]
+    fn returns_the_negative_answer_at_the_end_of_the_chain() {
+        let responses = [(
+            "www.example.com",
+            vec![cname("www.example.com", "gone.example.com")],
+        )];
+        let (result, _) = chase("www.example.com", &responses, MAX_CHAIN_LENGTH);
+
+        let response = result.unwrap();
+        assert_eq!(response.get_response_code(), ResponseCode::NXDomain);
+        assert_eq!(response.answers.len(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::dns::{
+    buffer_packets::{BytePacketBuffer, MAX_MESSAGE_SIZE},
+    dns_header::QueryResponseIndicator,
+    dns_message::DnsMessage,
+};
+
+/// Sends `bytes`, the serialized `request`, to `address` from a fresh ephemeral port and
+/// waits up to `timeout` for the matching response, returning `None` once it expires.
+/// Anything else arriving in the meantime is discarded.
+pub fn exchange(
+    bytes: &[u8],
+    request: &DnsMessage,
+    address: SocketAddr,
+    timeout: Duration,
+    exact_case: bool,
+) -> io::Result<Option<DnsMessage>> {
+    let local_address: SocketAddr = match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local_address)?;
+    // Lets the kernel drop datagrams from other sources and report ICMP errors
+    socket.connect(address)?;
+    socket.send(bytes)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut response = BytePacketBuffer::with_max_size(MAX_MESSAGE_SIZE);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (size, source) = match socket.recv_from(&mut response.buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        response.position = size;
+
+        if source != address {
+            eprintln!("Discarding response from unexpected source {}", source);
+            continue;
+        }
+        match DnsMessage::try_from(response.filled()) {
+            Ok(response) if matches(request, &response, exact_case) => return Ok(Some(response)),
+            Ok(_) => eprintln!("Discarding response from {} for another query", source),
+            Err(e) => eprintln!("Discarding unparseable response from {}: {}", source, e),
+        }
+    }
+}
+
+/// Whether `response` answers `request`: same ID and the same question. With `exact_case`
+/// (0x20 encoding) the name has to come back spelled exactly as sent.
+fn matches(request: &DnsMessage, response: &DnsMessage, exact_case: bool) -> bool {
+    let same_questions = response.questions.len() == request.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(&request.questions)
+            .all(|(answered, asked)| match exact_case {
+                true => answered == asked,
+                false => {
+                    answered.name.eq_ignore_case(&asked.name)
+                        && answered.query_type == asked.query_type
+                        && answered.resource_class == asked.resource_class
+                }
+            });
+    response.header.get_query_response_indicator() == QueryResponseIndicator::Response()
+        && response.header.get_packet_identifier() == request.header.get_packet_identifier()
+        && same_questions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::dns::{
+        dns_header::DnsHeaderFlag,
+        dns_message::ResourceRecord,
+        dns_question::{DomainName, QueryType, Question, ResourceClass, ResourceType},
+        dns_rdata::RData,
+    };
+
+    const REAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const FORGED: Ipv4Addr = Ipv4Addr::new(6, 6, 6, 6);
+
+    fn name(text: &str) -> DomainName {
+        DomainName {
+            content: text
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// The reply to `query` with a single address for its name.
+    fn reply(query: &DnsMessage, address: Ipv4Addr) -> DnsMessage {
+        let mut reply = query.clone();
+        reply
+            .header
+            .set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Response()));
+        reply.answers.push(ResourceRecord {
+            name: query.questions[0].name.clone(),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data: RData::A(address),
+        });
+        reply
+    }
+
+    /// An upstream that answers the first query it gets with each of `forge`'s replies,
+    /// then one from a different port, then some garbage, and only then the real reply.
+    fn upstream(forge: fn(&DnsMessage) -> Vec<DnsMessage>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, source) = socket.recv_from(&mut buf).unwrap();
+            let query = DnsMessage::try_from(&buf[..len]).unwrap();
+            let send = |message: &DnsMessage| {
+                let bytes = message.serialize_as_be().unwrap();
+                socket.send_to(&bytes, source).unwrap();
+            };
+
+            for forged in forge(&query) {
+                send(&forged);
+            }
+            let elsewhere = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let bytes = reply(&query, FORGED).serialize_as_be().unwrap();
+            elsewhere.send_to(&bytes, source).unwrap();
+            socket.send_to(&[0xFF; 5], source).unwrap();
+            send(&reply(&query, REAL));
+        });
+        address
+    }
+
+    fn query(owner: &str) -> DnsMessage {
+        let mut query = DnsMessage::query(Question::new(
+            name(owner),
+            QueryType::Record(ResourceType::A),
+        ));
+        query.header.set_packet_identifier(0x4242);
+        query
+    }
+
+    fn ask(query: &DnsMessage, address: SocketAddr, exact_case: bool) -> Option<DnsMessage> {
+        let bytes = query.serialize_as_be().unwrap();
+        exchange(&bytes, query, address, Duration::from_secs(5), exact_case).unwrap()
+    }
+
+    fn answered_address(response: &DnsMessage) -> &RData {
+        &response.answers[0].data
+    }
+
+    #[test]
+    fn discards_replies_to_other_queries() {
+        let address = upstream(|query| {
+            let mut wrong_id = reply(query, FORGED);
+            wrong_id.header.set_packet_identifier(0x4243);
+            let mut wrong_name = reply(query, FORGED);
+            wrong_name.questions[0].name = name("example.org");
+            let mut wrong_type = reply(query, FORGED);
+            wrong_type.questions[0].query_type = QueryType::Record(ResourceType::AAAA);
+            let mut not_a_reply = reply(query, FORGED);
+            not_a_reply
+                .header
+                .set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Query()));
+            vec![wrong_id, wrong_name, wrong_type, not_a_reply]
+        });
+
+        let response = ask(&query("example.com"), address, false).unwrap();
+        assert_eq!(answered_address(&response), &RData::A(REAL));
+    }
+
+    #[test]
+    fn discards_replies_in_another_case_when_it_must_match() {
+        let address = upstream(|query| {
+            let mut wrong_case = reply(query, FORGED);
+            wrong_case.questions[0].name = name("example.com");
+            vec![wrong_case]
+        });
+
+        let response = ask(&query("ExAmPLe.cOm"), address, true).unwrap();
+        assert_eq!(answered_address(&response), &RData::A(REAL));
+    }
+
+    #[test]
+    fn accepts_replies_in_another_case_otherwise() {
+        let address = upstream(|query| {
+            let mut other_case = reply(query, REAL);
+            other_case.questions[0].name = name("example.com");
+            vec![other_case]
+        });
+
+        let response = ask(&query("ExAmPLe.cOm"), address, false).unwrap();
+        assert_eq!(response.questions[0].name.to_string(), "example.com.");
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::dns::{
+    dns_message::DnsMessage,
+    dns_question::{DomainName, QueryType, Question, ResourceType},
+};
+
+use super::{exchange::exchange, resolver_error::ResolverError, upstream::Upstream, Resolver};
+
+/// The order in which upstreams are tried for each query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Always start with the first upstream and move down the list when it fails.
+    Failover,
+    /// Start one further along the list for every query.
+    RoundRobin,
+    /// Start at a random upstream.
+    Random,
+    /// Start at the upstream with the lowest smoothed RTT.
+    Fastest,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failover" => Ok(Strategy::Failover),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "random" => Ok(Strategy::Random),
+            "fastest" => Ok(Strategy::Fastest),
+            _ => Err(format!("unknown upstream strategy {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForwarderOptions {
+    /// How long the first attempt waits for an answer. Once every upstream has had a try,
+    /// the next round waits twice as long.
+    pub timeout: Duration,
+    /// Attempts made after the first one times out, each going to the next upstream.
+    pub retries: u32,
+    pub strategy: Strategy,
+    /// Timeouts in a row after which an upstream is marked dead.
+    pub max_failures: u32,
+    /// How often dead upstreams are probed to see whether they are back.
+    pub probe_interval: Duration,
+    /// Send query names in randomized case and require the reply to echo it (0x20 encoding).
+    /// Off by default because some servers don't preserve case.
+    pub randomize_case: bool,
+}
+
+impl Default for ForwarderOptions {
+    fn default() -> Self {
+        ForwarderOptions {
+            timeout: Duration::from_millis(1000),
+            retries: 2,
+            strategy: Strategy::Failover,
+            max_failures: 3,
+            probe_interval: Duration::from_secs(30),
+            randomize_case: false,
+        }
+    }
+}
+
+/// Sends queries to a list of upstream resolvers over UDP.
+#[derive(Debug)]
+pub struct Forwarder {
+    pub upstreams: Vec<Upstream>,
+    pub options: ForwarderOptions,
+    /// Where the next round-robin query starts.
+    next_upstream: AtomicUsize,
+}
+
+impl Forwarder {
+    /// `addresses` must not be empty.
+    pub fn new(addresses: Vec<SocketAddr>, options: ForwarderOptions) -> Self {
+        assert!(!addresses.is_empty(), "a forwarder needs an upstream");
+        Forwarder {
+            upstreams: addresses.into_iter().map(Upstream::new).collect(),
+            options,
+            next_upstream: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts a thread that regularly sends a query to each dead upstream and brings back
+    /// the ones that answer.
+    pub fn spawn_prober(self: &Arc<Self>) -> JoinHandle<()> {
+        let forwarder = Arc::clone(self);
+        thread::spawn(move || loop {
+            thread::sleep(forwarder.options.probe_interval);
+            for upstream in forwarder.upstreams.iter().filter(|u| u.is_dead()) {
+                forwarder.probe(upstream);
+            }
+        })
+    }
+
+    /// Indices into `upstreams` in the order they should be tried. Dead upstreams are left
+    /// out unless all of them are dead, in which case they are all worth a try.
+    fn select(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.upstreams.len())
+            .filter(|&i| !self.upstreams[i].is_dead())
+            .collect();
+        if order.is_empty() {
+            order = (0..self.upstreams.len()).collect();
+        }
+
+        match self.options.strategy {
+            Strategy::Failover => {}
+            Strategy::RoundRobin => {
+                let start = self.next_upstream.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(start);
+            }
+            Strategy::Random => order.shuffle(&mut rand::thread_rng()),
+            // Upstreams without an RTT yet go first so they get measured
+            Strategy::Fastest => {
+                order.sort_by_key(|&i| self.upstreams[i].get_srtt().unwrap_or_default())
+            }
+        }
+        order
+    }
+
+    /// Asks `upstream` for the root name servers, which any resolver can answer.
+    fn probe(&self, upstream: &Upstream) {
+        let mut probe = DnsMessage::query(Question::new(
+            DomainName::new(),
+            QueryType::Record(ResourceType::NS),
+        ));
+        probe.header.set_packet_identifier(rand::random());
+        let Ok(bytes) = probe.serialize_as_be() else {
+            return;
+        };
+
+        let sent = Instant::now();
+        if let Ok(Some(_)) = exchange(
+            &bytes,
+            &probe,
+            upstream.address,
+            self.options.timeout,
+            false,
+        ) {
+            upstream.record_success(sent.elapsed());
+            println!("Upstream {} is answering again", upstream.address);
+        }
+    }
+}
+
+impl Resolver for Forwarder {
+    /// Sends `request` upstream and waits for the reply, moving on to the next upstream
+    /// whenever an attempt goes unanswered. Every attempt uses a new random ID and source
+    /// port, and only a reply from the right address echoing both the ID and the question
+    /// is accepted, so forged responses have to guess all of them (RFC 5452).
+    fn resolve(&self, request: &DnsMessage) -> Result<DnsMessage, ResolverError> {
+        let order = self.select();
+        let attempts = self.options.retries + 1;
+        for attempt in 0..attempts as usize {
+            let upstream = &self.upstreams[order[attempt % order.len()]];
+            // Every upstream gets a try before any timeout is doubled
+            let round = (attempt / order.len()).min(16) as u32;
+            let timeout = self.options.timeout.saturating_mul(1 << round);
+
+            let mut upstream_request = request.clone();
+            upstream_request
+                .header
+                .set_packet_identifier(rand::random());
+            if self.options.randomize_case {
+                for question in &mut upstream_request.questions {
+                    question.name = randomize_case(&question.name);
+                }
+            }
+            let bytes = upstream_request.serialize_as_be()?;
+
+            let sent = Instant::now();
+            match exchange(
+                &bytes,
+                &upstream_request,
+                upstream.address,
+                timeout,
+                self.options.randomize_case,
+            ) {
+                Ok(Some(mut response)) => {
+                    upstream.record_success(sent.elapsed());
+                    restore_case(&mut response, request);
+                    return Ok(response);
+                }
+                Ok(None) => eprintln!(
+                    "No response from {} within {:?} (attempt {} of {})",
+                    upstream.address,
+                    timeout,
+                    attempt + 1,
+                    attempts
+                ),
+                Err(e) => eprintln!("Failed to query {}: {}", upstream.address, e),
+            }
+            if upstream.record_failure(timeout, self.options.max_failures) {
+                eprintln!("Marking upstream {} as dead", upstream.address);
+            }
+        }
+
+        Err(ResolverError::Timeout { attempts })
+    }
+}
+
+/// Flips the case of each letter at random (draft-vixie-dnsext-dns0x20), adding up to one
+/// bit of entropy per letter that a forged response also has to match.
+fn randomize_case(name: &DomainName) -> DomainName {
+    let mut rng = rand::thread_rng();
+    let content = name
+        .content
+        .iter()
+        .map(|label| {
+            label
+                .iter()
+                .map(|c| match rng.gen::<bool>() {
+                    true => c.to_ascii_uppercase(),
+                    false => c.to_ascii_lowercase(),
+                })
+                .collect()
+        })
+        .collect();
+    DomainName { content }
+}
+
+/// Puts the names from `request` back where upstream echoed our randomized spelling, so the
+/// client sees its question the way it asked it.
+fn restore_case(response: &mut DnsMessage, request: &DnsMessage) {
+    for (answered, asked) in response.questions.iter_mut().zip(&request.questions) {
+        for record in response
+            .answers
+            .iter_mut()
+            .chain(response.authority.iter_mut())
+            .chain(response.extra.iter_mut())
+        {
+            if record.name.eq_ignore_case(&asked.name) {
+                record.name = asked.name.clone();
+            }
+        }
+        answered.name = asked.name.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, UdpSocket};
+
+    use super::*;
+    use crate::dns::{dns_message::ResourceRecord, dns_question::ResourceClass, dns_rdata::RData};
+
+    fn name(text: &str) -> DomainName {
+        DomainName {
+            content: text
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn query() -> DnsMessage {
+        DnsMessage::query(Question::new(
+            name("example.com"),
+            QueryType::Record(ResourceType::A),
+        ))
+    }
+
+    fn options() -> ForwarderOptions {
+        ForwarderOptions {
+            timeout: Duration::from_millis(50),
+            retries: 1,
+            ..ForwarderOptions::default()
+        }
+    }
+
+    #[test]
+    fn times_out_when_upstream_never_replies() {
+        // Bound but never read, so queries go unanswered
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forwarder = Forwarder::new(vec![upstream.local_addr().unwrap()], options());
+
+        let result = forwarder.resolve(&query());
+        assert!(matches!(
+            result,
+            Err(ResolverError::Timeout { attempts: 2 })
+        ));
+    }
+
+    #[test]
+    fn times_out_when_replies_come_too_late() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, source)) = upstream.recv_from(&mut buf) {
+                // Echoing the query back as a reply, but only once the attempt has given up
+                thread::sleep(Duration::from_millis(250));
+                buf[2] |= 0x80;
+                let _ = upstream.send_to(&buf[..len], source);
+            }
+        });
+        let forwarder = Forwarder::new(vec![address], options());
+
+        let result = forwarder.resolve(&query());
+        assert!(matches!(
+            result,
+            Err(ResolverError::Timeout { attempts: 2 })
+        ));
+    }
+
+    /// A forwarder for three upstreams that are never actually asked.
+    fn forwarder(strategy: Strategy) -> Forwarder {
+        let addresses = ["192.0.2.1:53", "192.0.2.2:53", "192.0.2.3:53"];
+        Forwarder::new(
+            addresses.iter().map(|a| a.parse().unwrap()).collect(),
+            ForwarderOptions {
+                strategy,
+                max_failures: 1,
+                ..options()
+            },
+        )
+    }
+
+    fn kill(upstream: &Upstream) {
+        upstream.record_failure(Duration::from_secs(1), 1);
+    }
+
+    #[test]
+    fn fails_over_in_list_order() {
+        let forwarder = forwarder(Strategy::Failover);
+        assert_eq!(forwarder.select(), [0, 1, 2]);
+        assert_eq!(forwarder.select(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn rotates_the_start_for_round_robin() {
+        let forwarder = forwarder(Strategy::RoundRobin);
+        assert_eq!(forwarder.select(), [0, 1, 2]);
+        assert_eq!(forwarder.select(), [1, 2, 0]);
+        assert_eq!(forwarder.select(), [2, 0, 1]);
+        assert_eq!(forwarder.select(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn tries_every_upstream_in_random_order() {
+        let forwarder = forwarder(Strategy::Random);
+        let mut order = forwarder.select();
+        order.sort();
+        assert_eq!(order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn prefers_the_lowest_srtt() {
+        let forwarder = forwarder(Strategy::Fastest);
+        forwarder.upstreams[0].record_success(Duration::from_millis(30));
+        forwarder.upstreams[1].record_success(Duration::from_millis(10));
+        // Not measured yet, so it goes first
+        assert_eq!(forwarder.select(), [2, 1, 0]);
+
+        forwarder.upstreams[2].record_success(Duration::from_millis(20));
+        assert_eq!(forwarder.select(), [1, 2, 0]);
+    }
+
+    #[test]
+    fn skips_dead_upstreams() {
+        let failover = forwarder(Strategy::Failover);
+        kill(&failover.upstreams[0]);
+        assert_eq!(failover.select(), [1, 2]);
+
+        let round_robin = forwarder(Strategy::RoundRobin);
+        kill(&round_robin.upstreams[1]);
+        assert_eq!(round_robin.select(), [0, 2]);
+        assert_eq!(round_robin.select(), [2, 0]);
+    }
+
+    #[test]
+    fn tries_all_upstreams_once_all_are_dead() {
+        let forwarder = forwarder(Strategy::Failover);
+        for upstream in &forwarder.upstreams {
+            kill(upstream);
+        }
+        assert_eq!(forwarder.select(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn revives_dead_upstreams_that_answer_a_probe() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, source)) = upstream.recv_from(&mut buf) {
+                // Echoing the query back is a good enough reply
+                buf[2] |= 0x80;
+                let _ = upstream.send_to(&buf[..len], source);
+            }
+        });
+        let forwarder = Forwarder::new(vec![address], options());
+        kill(&forwarder.upstreams[0]);
+        assert!(forwarder.upstreams[0].is_dead());
+
+        forwarder.probe(&forwarder.upstreams[0]);
+        assert!(!forwarder.upstreams[0].is_dead());
+    }
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    #[test]
+    fn randomizes_only_the_case() {
+        let name = name("www.example-1.com");
+        let randomized = randomize_case(&name);
+        assert!(randomized.eq_ignore_case(&name));
+    }
+
+    #[test]
+    fn restores_the_clients_spelling() {
+        let request = DnsMessage::query(Question::new(
+            name("Www.Example.com"),
+            QueryType::Record(ResourceType::A),
+        ));
+        let mut response = DnsMessage::query(Question::new(
+            name("wWW.eXamPLE.CoM"),
+            QueryType::Record(ResourceType::A),
+        ));
+        response.answers = vec![
+            record("wWW.eXamPLE.CoM", RData::CNAME(name("WEB.example.com"))),
+            record("WEB.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+        ];
+
+        restore_case(&mut response, &request);
+        assert_eq!(response.questions[0].name.to_string(), "Www.Example.com.");
+        assert_eq!(response.answers[0].name.to_string(), "Www.Example.com.");
+        // Names other than the question's are left as upstream sent them
+        assert_eq!(response.answers[1].name.to_string(), "WEB.example.com.");
+    }
+
+    #[test]
+    fn answers_with_the_clients_spelling_when_randomizing_case() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, source) = upstream.recv_from(&mut buf).unwrap();
+            // Echoes the randomized spelling back, as servers preserving case do
+            buf[2] |= 0x80;
+            upstream.send_to(&buf[..len], source).unwrap();
+        });
+        let forwarder = Forwarder::new(
+            vec![address],
+            ForwarderOptions {
+                randomize_case: true,
+                ..options()
+            },
+        );
+
+        let mut request = query();
+        request.questions[0].name = name("eXample.com");
+        let response = forwarder.resolve(&request).unwrap();
+        assert_eq!(response.questions[0].name.to_string(), "eXample.com.");
+    }
+}
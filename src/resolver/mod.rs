@@ -0,0 +1,19 @@
+use std::fmt::Debug;
+
+use crate::dns::dns_message::DnsMessage;
+
+use self::resolver_error::ResolverError;
+
+pub mod cache;
+pub mod chase;
+pub mod exchange;
+pub mod forwarder;
+pub mod recursive;
+pub mod resolver_error;
+pub mod upstream;
+
+/// Finds the answer to a query somewhere else, by forwarding or by recursion.
+pub trait Resolver: Debug + Send + Sync {
+    /// Answers the single question in `request`.
+    fn resolve(&self, request: &DnsMessage) -> Result<DnsMessage, ResolverError>;
+}
@@ -0,0 +1,598 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::dns::{
+    dns_edns::{Edns, DEFAULT_EDNS_UDP_SIZE},
+    dns_header::{DnsHeaderFlag, ResponseCode},
+    dns_message::DnsMessage,
+    dns_question::{DomainName, QueryType, Question, ResourceType},
+    dns_rdata::RData,
+};
+
+use super::{exchange::exchange, resolver_error::ResolverError, Resolver};
+
+/// IPv4 addresses of the root servers a.root-servers.net through m.root-servers.net.
+const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+#[derive(Debug, Clone)]
+pub struct RecursorOptions {
+    /// Servers for the root zone, where every resolution starts.
+    pub root_hints: Vec<SocketAddr>,
+    /// Port used for name servers learned from referrals, which only come with an address.
+    pub port: u16,
+    /// How long to wait for each name server before trying the next one.
+    pub timeout: Duration,
+    /// Queries one client question may cause in total, name server lookups included.
+    pub max_queries: u32,
+    /// How deeply name server address lookups may nest inside each other.
+    pub max_depth: usize,
+}
+
+impl Default for RecursorOptions {
+    fn default() -> Self {
+        RecursorOptions {
+            root_hints: ROOT_SERVERS
+                .iter()
+                .map(|&address| SocketAddr::new(IpAddr::V4(address), 53))
+                .collect(),
+            port: 53,
+            timeout: Duration::from_millis(800),
+            max_queries: 64,
+            max_depth: 6,
+        }
+    }
+}
+
+/// Reads root hints in the format of `named.root`: master file lines whose A and AAAA
+/// records give the root servers' addresses. NS lines and comments are skipped.
+pub fn parse_root_hints(hints: &str, port: u16) -> Vec<SocketAddr> {
+    hints
+        .lines()
+        .filter_map(|line| {
+            let line = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let type_index = fields.iter().position(|field| {
+                field.eq_ignore_ascii_case("A") || field.eq_ignore_ascii_case("AAAA")
+            })?;
+            let address: IpAddr = fields.get(type_index + 1)?.parse().ok()?;
+            Some(SocketAddr::new(address, port))
+        })
+        .collect()
+}
+
+/// A server for the zone currently being asked. Its addresses may still have to be looked
+/// up when the referral came without glue.
+#[derive(Debug, Clone)]
+struct NameServer {
+    name: DomainName,
+    addresses: Option<Vec<SocketAddr>>,
+}
+
+/// What a name server's response means for the resolution.
+enum Step {
+    /// The final answer, positive or negative.
+    Answer(DnsMessage),
+    /// Ask these servers for `zone`, which is closer to the name.
+    Referral {
+        zone: DomainName,
+        servers: Vec<NameServer>,
+    },
+}
+
+/// Resolves names itself by starting at the root servers and following referrals down to
+/// the servers authoritative for the name (RFC 1034 section 5.3.3).
+#[derive(Debug)]
+pub struct Recursor {
+    pub options: RecursorOptions,
+}
+
+impl Recursor {
+    pub fn new(options: RecursorOptions) -> Self {
+        Recursor { options }
+    }
+
+    /// Follows referrals from the root down for `question`. `depth` counts the name server
+    /// lookups this one is nested in, and `budget` the queries left for the client question.
+    fn resolve_question(
+        &self,
+        question: &Question,
+        depth: usize,
+        budget: &mut u32,
+    ) -> Result<DnsMessage, ResolverError> {
+        if depth > self.options.max_depth {
+            return Err(ResolverError::TooDeep(self.options.max_depth));
+        }
+
+        let mut zone = DomainName::new();
+        let mut servers: Vec<NameServer> = self
+            .options
+            .root_hints
+            .iter()
+            .map(|&address| NameServer {
+                name: DomainName::new(),
+                addresses: Some(vec![address]),
+            })
+            .collect();
+
+        loop {
+            match self.ask(&zone, &servers, question, depth, budget)? {
+                Step::Answer(response) => return Ok(response),
+                Step::Referral {
+                    zone: child_zone,
+                    servers: child_servers,
+                } => {
+                    println!("Referred from {} to {}", zone, child_zone);
+                    zone = child_zone;
+                    servers = child_servers;
+                }
+            }
+        }
+    }
+
+    /// Asks each of `servers` in turn about `question` until one gives a usable response.
+    fn ask(
+        &self,
+        zone: &DomainName,
+        servers: &[NameServer],
+        question: &Question,
+        depth: usize,
+        budget: &mut u32,
+    ) -> Result<Step, ResolverError> {
+        for server in servers {
+            let addresses = match &server.addresses {
+                Some(addresses) => addresses.clone(),
+                None => match self.lookup_addresses(&server.name, depth, budget) {
+                    Ok(addresses) => addresses,
+                    // Limits on the whole resolution end it, whichever server hit them
+                    Err(
+                        e @ (ResolverError::QueryBudgetExceeded(_) | ResolverError::TooDeep(_)),
+                    ) => return Err(e),
+                    Err(e) => {
+                        eprintln!("Failed to find the address of {}: {}", server.name, e);
+                        continue;
+                    }
+                },
+            };
+
+            for address in addresses {
+                if *budget == 0 {
+                    return Err(ResolverError::QueryBudgetExceeded(self.options.max_queries));
+                }
+                *budget -= 1;
+
+                let response = match self.query(address, question) {
+                    Ok(Some(response)) => response,
+                    Ok(None) => {
+                        eprintln!("No response from {} for zone {}", address, zone);
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to query {}: {}", address, e);
+                        continue;
+                    }
+                };
+                match self.interpret(zone, question, response) {
+                    Some(step) => return Ok(step),
+                    None => eprintln!("Unusable response from {} for zone {}", address, zone),
+                }
+            }
+        }
+
+        Err(ResolverError::NoUsableServer(zone.to_string()))
+    }
+
+    /// Sends `question` to a single name server, without asking it to recurse.
+    fn query(
+        &self,
+        address: SocketAddr,
+        question: &Question,
+    ) -> Result<Option<DnsMessage>, ResolverError> {
+        let mut request = DnsMessage::query(question.clone());
+        request.header.set_packet_identifier(rand::random());
+        // Referrals with plenty of glue rarely fit in 512 bytes
+        request.edns = Some(Edns::new(DEFAULT_EDNS_UDP_SIZE));
+        let bytes = request.serialize_as_be()?;
+        Ok(exchange(
+            &bytes,
+            &request,
+            address,
+            self.options.timeout,
+            false,
+        )?)
+    }
+
+    /// Works out what a response from a server for `zone` tells us. `None` means the server
+    /// is of no use (an error, or a referral that doesn't get closer to the name) and the
+    /// next one should be tried.
+    fn interpret(
+        &self,
+        zone: &DomainName,
+        question: &Question,
+        response: DnsMessage,
+    ) -> Option<Step> {
+        match response.get_response_code() {
+            ResponseCode::NXDomain => return Some(Step::Answer(response)),
+            ResponseCode::NoError => {}
+            _ => return None,
+        }
+        if !response.answers.is_empty() {
+            return Some(Step::Answer(response));
+        }
+
+        // A referral names the servers of a zone below the current one that contains the name
+        let child_zone = response
+            .authority
+            .iter()
+            .find(|record| {
+                record.resource_type() == ResourceType::NS
+                    && record.name.content.len() > zone.content.len()
+                    && record.name.is_subdomain_of(zone)
+                    && question.name.is_subdomain_of(&record.name)
+            })
+            .map(|record| record.name.clone());
+        if let Some(child_zone) = child_zone {
+            let servers = self.referral_servers(zone, &child_zone, &response);
+            return (!servers.is_empty()).then_some(Step::Referral {
+                zone: child_zone,
+                servers,
+            });
+        }
+
+        // NODATA: the name exists but has nothing of this type
+        let has_soa = response
+            .authority
+            .iter()
+            .any(|record| record.resource_type() == ResourceType::SOA);
+        (has_soa || response.header.get_authoritative_answer()).then_some(Step::Answer(response))
+    }
+
+    /// The name servers of `child_zone` listed in a referral from a server for `zone`, with
+    /// the addresses from glue records where there are any. Glue is only believed for names
+    /// inside `zone`, the data that server is responsible for.
+    fn referral_servers(
+        &self,
+        zone: &DomainName,
+        child_zone: &DomainName,
+        response: &DnsMessage,
+    ) -> Vec<NameServer> {
+        let mut servers: Vec<NameServer> = response
+            .authority
+            .iter()
+            .filter(|record| record.name.eq_ignore_case(child_zone))
+            .filter_map(|record| match &record.data {
+                RData::NS(name) => Some(name.clone()),
+                _ => None,
+            })
+            .filter_map(|name| {
+                let glue: Vec<SocketAddr> = response
+                    .extra
+                    .iter()
+                    .filter(|record| {
+                        record.name.eq_ignore_case(&name) && record.name.is_subdomain_of(zone)
+                    })
+                    .filter_map(|record| match record.data {
+                        RData::A(address) => Some(IpAddr::V4(address)),
+                        RData::AAAA(address) => Some(IpAddr::V6(address)),
+                        _ => None,
+                    })
+                    .map(|address| SocketAddr::new(address, self.options.port))
+                    .collect();
+                if !glue.is_empty() {
+                    return Some(NameServer {
+                        name,
+                        addresses: Some(glue),
+                    });
+                }
+                // Without glue, a server inside the zone it serves can never be reached
+                (!name.is_subdomain_of(child_zone)).then_some(NameServer {
+                    name,
+                    addresses: None,
+                })
+            })
+            .collect();
+
+        // Servers we can ask right away come first; IPv4 before IPv6 for each of them
+        servers.sort_by_key(|server| server.addresses.is_none());
+        for server in &mut servers {
+            if let Some(addresses) = &mut server.addresses {
+                addresses.sort_by_key(|address| address.is_ipv6());
+            }
+        }
+        servers
+    }
+
+    /// Resolves the IPv4 addresses of a name server that came without glue.
+    fn lookup_addresses(
+        &self,
+        name: &DomainName,
+        depth: usize,
+        budget: &mut u32,
+    ) -> Result<Vec<SocketAddr>, ResolverError> {
+        let question = Question::new(name.clone(), QueryType::Record(ResourceType::A));
+        let response = self.resolve_question(&question, depth + 1, budget)?;
+        let addresses: Vec<SocketAddr> = response
+            .answers
+            .iter()
+            .filter_map(|record| match record.data {
+                RData::A(address) => Some(SocketAddr::new(IpAddr::V4(address), self.options.port)),
+                _ => None,
+            })
+            .collect();
+        match addresses.is_empty() {
+            true => Err(ResolverError::NoUsableServer(name.to_string())),
+            false => Ok(addresses),
+        }
+    }
+}
+
+impl Resolver for Recursor {
+    fn resolve(&self, request: &DnsMessage) -> Result<DnsMessage, ResolverError> {
+        let mut budget = self.options.max_queries;
+        let mut response = self.resolve_question(&request.questions[0], 0, &mut budget)?;
+
+        // The answer came from elsewhere, and we are the ones doing the recursion
+        response.header.set_header_flag(DnsHeaderFlag::Aa(false));
+        response.header.set_header_flag(DnsHeaderFlag::Ra(true));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+    use crate::dns::{
+        dns_header::QueryResponseIndicator, dns_message::ResourceRecord,
+        dns_question::ResourceClass,
+    };
+
+    fn name(name: &str) -> DomainName {
+        DomainName {
+            content: name
+                .split('.')
+                .map(|label| label.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn www_query() -> DnsMessage {
+        DnsMessage::query(Question::new(
+            name("www.example.com"),
+            QueryType::Record(ResourceType::A),
+        ))
+    }
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: name(owner),
+            resource_class: ResourceClass::IN,
+            ttl: 300,
+            data,
+        }
+    }
+
+    fn address(owner: &str, address: Ipv4Addr) -> ResourceRecord {
+        record(owner, RData::A(address))
+    }
+
+    /// What a fake server sends back; the ID, question and QR bit are filled in for it.
+    #[derive(Default)]
+    struct Reply {
+        authoritative: bool,
+        answers: Vec<ResourceRecord>,
+        authority: Vec<ResourceRecord>,
+        extra: Vec<ResourceRecord>,
+    }
+
+    /// A referral to `zone`, served by `servers`, each with its glue if it has any.
+    fn referral(zone: &str, servers: &[(&str, Option<Ipv4Addr>)]) -> Reply {
+        Reply {
+            authority: servers
+                .iter()
+                .map(|(server, _)| record(zone, RData::NS(name(server))))
+                .collect(),
+            extra: servers
+                .iter()
+                .filter_map(|(server, glue)| glue.map(|glue| address(server, glue)))
+                .collect(),
+            ..Reply::default()
+        }
+    }
+
+    fn answer(owner: &str, ip: Ipv4Addr) -> Reply {
+        Reply {
+            authoritative: true,
+            answers: vec![address(owner, ip)],
+            ..Reply::default()
+        }
+    }
+
+    /// Runs a fake name server on `ip` and `port` that replies with whatever `reply` makes
+    /// of the lowercased question name, or REFUSED for `None`. Returns the names it gets
+    /// asked about.
+    fn serve(
+        ip: Ipv4Addr,
+        port: u16,
+        reply: impl Fn(&str) -> Option<Reply> + Send + 'static,
+    ) -> Arc<Mutex<Vec<String>>> {
+        let socket = UdpSocket::bind((ip, port)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let asked = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&asked);
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok((len, source)) = socket.recv_from(&mut buf) {
+                let request = DnsMessage::try_from(&buf[..len]).unwrap();
+                let question = request.questions[0].name.to_lowercase_key();
+                log.lock().unwrap().push(question.clone());
+
+                let mut response = DnsMessage {
+                    edns: None,
+                    ..request
+                };
+                response
+                    .header
+                    .set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Response()));
+                match reply(&question) {
+                    Some(reply) => {
+                        response
+                            .header
+                            .set_header_flag(DnsHeaderFlag::Aa(reply.authoritative));
+                        response.answers = reply.answers;
+                        response.authority = reply.authority;
+                        response.extra = reply.extra;
+                    }
+                    None => response.set_response_code(ResponseCode::Refused),
+                }
+                let bytes = response.serialize_as_be().unwrap();
+                socket.send_to(&bytes, source).unwrap();
+            }
+        });
+        asked
+    }
+
+    const ROOT: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
+    const COM: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+    const NET: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 3);
+    const LEAF: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 4);
+    const POISON: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 5);
+    const WWW: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// A port free on 127.0.0.1, where the root server is run; all the other servers use
+    /// the same port on their own addresses, since referrals only carry addresses.
+    fn free_port() -> u16 {
+        UdpSocket::bind((ROOT, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Root and TLD servers for com and net, with the com server referring example.com
+    /// as `example_com` has it.
+    fn hierarchy(port: u16, example_com: &'static [(&'static str, Option<Ipv4Addr>)]) -> Recursor {
+        serve(ROOT, port, |question| match question {
+            q if q.ends_with("com.") => Some(referral("com", &[("a.gtld.com", Some(COM))])),
+            q if q.ends_with("net.") => Some(referral("net", &[("a.gtld.net", Some(NET))])),
+            _ => None,
+        });
+        serve(COM, port, move |question| match question {
+            q if q.ends_with("example.com.") => Some(referral("example.com", example_com)),
+            _ => None,
+        });
+        Recursor::new(RecursorOptions {
+            root_hints: vec![SocketAddr::new(IpAddr::V4(ROOT), port)],
+            port,
+            timeout: Duration::from_millis(500),
+            ..RecursorOptions::default()
+        })
+    }
+
+    fn serve_leaf(port: u16) -> Arc<Mutex<Vec<String>>> {
+        serve(LEAF, port, |question| match question {
+            "www.example.com." => Some(answer("www.example.com", WWW)),
+            _ => None,
+        })
+    }
+
+    /// Serves the net zone's example.net, whose name server only has an address in the zone
+    /// itself.
+    fn serve_example_net(port: u16) {
+        serve(NET, port, |question| match question {
+            "ns.example.net." => Some(answer("ns.example.net", LEAF)),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn follows_referrals_from_the_root() {
+        let port = free_port();
+        let recursor = hierarchy(port, &[("ns1.example.com", Some(LEAF))]);
+        let leaf = serve_leaf(port);
+
+        let response = recursor.resolve(&www_query()).unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data, RData::A(WWW));
+        assert!(!response.header.get_authoritative_answer());
+        assert!(response.header.get_recursion_available());
+        assert_eq!(*leaf.lock().unwrap(), vec!["www.example.com."]);
+    }
+
+    #[test]
+    fn looks_up_name_servers_outside_the_zone() {
+        let port = free_port();
+        let recursor = hierarchy(port, &[("ns.example.net", None)]);
+        serve_example_net(port);
+        let leaf = serve_leaf(port);
+
+        let response = recursor.resolve(&www_query()).unwrap();
+        assert_eq!(response.answers[0].data, RData::A(WWW));
+        assert_eq!(*leaf.lock().unwrap(), vec!["www.example.com."]);
+    }
+
+    #[test]
+    fn ignores_glue_outside_the_referring_zone() {
+        let port = free_port();
+        // The com servers have no say over example.net, so its address must be looked up
+        let recursor = hierarchy(port, &[("ns.example.net", Some(POISON))]);
+        serve_example_net(port);
+        let leaf = serve_leaf(port);
+        let poison = serve(POISON, port, |_| Some(answer("www.example.com", ROOT)));
+
+        let response = recursor.resolve(&www_query()).unwrap();
+        assert_eq!(response.answers[0].data, RData::A(WWW));
+        assert_eq!(leaf.lock().unwrap().len(), 1);
+        assert!(poison.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn gives_up_when_the_query_budget_runs_out() {
+        let port = free_port();
+        let mut recursor = hierarchy(port, &[("ns1.example.com", Some(LEAF))]);
+        recursor.options.max_queries = 2;
+        let leaf = serve_leaf(port);
+
+        let result = recursor.resolve(&www_query());
+        assert!(matches!(result, Err(ResolverError::QueryBudgetExceeded(2))));
+        assert!(leaf.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn gives_up_when_name_server_lookups_nest_too_deeply() {
+        let port = free_port();
+        // Each zone's server is only known by a name in the other zone
+        let recursor = hierarchy(port, &[("ns.example.net", None)]);
+        serve(NET, port, |question| match question {
+            q if q.ends_with("example.net.") => {
+                Some(referral("example.net", &[("ns.example.com", None)]))
+            }
+            _ => None,
+        });
+
+        let result = recursor.resolve(&www_query());
+        assert!(matches!(result, Err(ResolverError::TooDeep(6))));
+    }
+}
@@ -0,0 +1,26 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::dns::dns_error::DnsSerializeError;
+
+/// Everything that can go wrong while getting an answer from another server.
+#[derive(Debug, Error)]
+pub enum ResolverError {
+    #[error("no upstream answered after {attempts} attempts")]
+    Timeout { attempts: u32 },
+    #[error("gave up after sending {0} queries")]
+    QueryBudgetExceeded(u32),
+    #[error("name server lookups nested more than {0} levels deep")]
+    TooDeep(usize),
+    #[error("no name server gave a usable answer for {0}")]
+    NoUsableServer(String),
+    #[error("CNAME chain loops back to {0}")]
+    ChainLoop(String),
+    #[error("CNAME chain longer than {0} links")]
+    ChainTooLong(usize),
+    #[error("network error talking to upstream: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not build the upstream query: {0}")]
+    Serialize(#[from] DnsSerializeError),
+}
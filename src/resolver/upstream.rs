@@ -0,0 +1,122 @@
+use std::{net::SocketAddr, sync::Mutex, time::Duration};
+
+/// One upstream resolver and what we have learned about it from the queries sent so far.
+#[derive(Debug)]
+pub struct Upstream {
+    pub address: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Smoothed round trip time, `None` until the first answer or timeout.
+    srtt: Option<Duration>,
+    consecutive_failures: u32,
+    /// Dead upstreams are skipped until a probe gets an answer from them again.
+    dead: bool,
+}
+
+impl Upstream {
+    pub fn new(address: SocketAddr) -> Self {
+        Upstream {
+            address,
+            health: Mutex::new(Health::default()),
+        }
+    }
+
+    pub fn get_srtt(&self) -> Option<Duration> {
+        self.health.lock().unwrap().srtt
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health.lock().unwrap().dead
+    }
+
+    /// Records an answer that took `rtt` to arrive, bringing the upstream back to life.
+    pub fn record_success(&self, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(Self::smooth(health.srtt, rtt));
+        health.consecutive_failures = 0;
+        health.dead = false;
+    }
+
+    /// Records a query that went unanswered for `timeout`, which also counts against the
+    /// smoothed RTT. Returns true if this failure is the one that marked the upstream dead.
+    pub fn record_failure(&self, timeout: Duration, max_failures: u32) -> bool {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(Self::smooth(health.srtt, timeout));
+        health.consecutive_failures += 1;
+
+        let was_dead = health.dead;
+        health.dead = health.consecutive_failures >= max_failures;
+        health.dead && !was_dead
+    }
+
+    /// Exponentially weighted moving average with a gain of 1/8, as for TCP (RFC 6298).
+    fn smooth(srtt: Option<Duration>, sample: Duration) -> Duration {
+        match srtt {
+            Some(srtt) => srtt * 7 / 8 + sample / 8,
+            None => sample,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(800);
+
+    fn upstream() -> Upstream {
+        Upstream::new("192.0.2.1:53".parse().unwrap())
+    }
+
+    #[test]
+    fn dies_after_max_failures_in_a_row() {
+        let upstream = upstream();
+        assert!(!upstream.record_failure(TIMEOUT, 3));
+        assert!(!upstream.record_failure(TIMEOUT, 3));
+        assert!(!upstream.is_dead());
+
+        // Only the failure that kills it reports so
+        assert!(upstream.record_failure(TIMEOUT, 3));
+        assert!(upstream.is_dead());
+        assert!(!upstream.record_failure(TIMEOUT, 3));
+        assert!(upstream.is_dead());
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let upstream = upstream();
+        upstream.record_failure(TIMEOUT, 2);
+        upstream.record_success(Duration::from_millis(20));
+        assert!(!upstream.record_failure(TIMEOUT, 2));
+        assert!(!upstream.is_dead());
+    }
+
+    #[test]
+    fn comes_back_to_life_on_an_answer() {
+        let upstream = upstream();
+        upstream.record_failure(TIMEOUT, 1);
+        assert!(upstream.is_dead());
+
+        upstream.record_success(Duration::from_millis(20));
+        assert!(!upstream.is_dead());
+        // And has to fail all over again to die
+        assert!(upstream.record_failure(TIMEOUT, 1));
+    }
+
+    #[test]
+    fn smooths_round_trip_times() {
+        let upstream = upstream();
+        assert_eq!(upstream.get_srtt(), None);
+
+        upstream.record_success(Duration::from_millis(80));
+        assert_eq!(upstream.get_srtt(), Some(Duration::from_millis(80)));
+        upstream.record_success(Duration::from_millis(160));
+        assert_eq!(upstream.get_srtt(), Some(Duration::from_millis(90)));
+        // Timeouts count as samples too
+        upstream.record_failure(TIMEOUT, 3);
+        assert_eq!(upstream.get_srtt(), Some(Duration::from_micros(178_750)));
+    }
+}
@@ -0,0 +1,554 @@
+use std::sync::Arc;
+
+use bytes::BytesMut;
+
+use crate::{
+    dns::{
+        buffer_packets::{MAX_MESSAGE_SIZE, UDP_MESSAGE_SIZE},
+        dns_edns::{Edns, EDNS_VERSION},
+        dns_header::{
+            DnsHeader, DnsHeaderFlag, OperationCode, QueryResponseIndicator, ResponseCode,
+        },
+        dns_message::{DnsMessage, SerializeOptions},
+        dns_question::{Question, ResourceClass},
+    },
+    resolver::{
+        cache::Cache,
+        chase::{self, MAX_CHAIN_LENGTH},
+        resolver_error::ResolverError,
+        Resolver,
+    },
+    zone::catalog::Catalog,
+};
+
+/// The transport a request arrived on, which decides how large the response may be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Turns request bytes into response bytes. Shared by the UDP and TCP listeners so both
+/// answer identically.
+#[derive(Debug, Clone)]
+pub struct QueryHandler {
+    /// Where questions outside our zones get answered, by forwarding or recursion. Without
+    /// a resolver or zones every question gets a canned answer.
+    pub resolver: Option<Arc<dyn Resolver>>,
+    /// Zones answered authoritatively from master files.
+    pub zones: Option<Arc<Catalog>>,
+    /// Answers from upstream, reused until their TTLs run out.
+    pub cache: Option<Arc<Cache>>,
+    pub serialize_options: SerializeOptions,
+    /// Payload size advertised to EDNS clients and upstreams
+    pub edns_udp_size: u16,
+}
+
+impl QueryHandler {
+    /// Builds the response to `request`, or `None` if there isn't even a header to reply to.
+    pub fn handle(&self, request: &[u8], transport: Transport) -> Option<BytesMut> {
+        let mut dns_msg = match DnsMessage::try_from(request) {
+            Ok(dns_msg) => dns_msg,
+            Err(e) => {
+                eprintln!("Failed to parse request: {}", e);
+                // Without a complete header there is no ID to answer to
+                let header = request
+                    .get(..12)
+                    .and_then(|bytes| DnsHeader::try_from(bytes).ok())?;
+                let response = DnsMessage::error_response(header, ResponseCode::FormErr)
+                    .serialize_as_be()
+                    .expect("header-only responses always fit");
+                return Some(response);
+            }
+        };
+
+        // The response is built in place of the request, so none of the records the client
+        // sent may carry over into it
+        dns_msg.answers.clear();
+        dns_msg.authority.clear();
+        dns_msg.extra.clear();
+        dns_msg.header.answer_record_count = 0;
+        dns_msg.header.authority_record_count = 0;
+        dns_msg.header.additional_record_count = 0;
+
+        // Only clients that sent an OPT record get one back, and with our own size
+        let request_edns = dns_msg.edns.take();
+        if let Some(request_edns) = &request_edns {
+            let mut edns = Edns::new(self.edns_udp_size);
+            edns.dnssec_ok = request_edns.dnssec_ok;
+            dns_msg.edns = Some(edns);
+
+            if request_edns.version != EDNS_VERSION {
+                let mut response =
+                    DnsMessage::error_response(dns_msg.header, ResponseCode::NoError);
+                response.edns = dns_msg.edns;
+                response.set_response_code(ResponseCode::BADVERS);
+                let response = response
+                    .serialize_as_be()
+                    .expect("header-only responses always fit");
+                return Some(response);
+            }
+        }
+        let max_response_size = match transport {
+            // Payload sizes below 512 are treated as 512 (RFC 6891 section 6.2.3)
+            Transport::Udp => request_edns
+                .as_ref()
+                .map(|edns| edns.udp_payload_size.min(self.edns_udp_size).max(512) as usize)
+                .unwrap_or(UDP_MESSAGE_SIZE),
+            Transport::Tcp => MAX_MESSAGE_SIZE,
+        };
+
+        let resolved = match (&self.resolver, &self.zones) {
+            (None, None) => {
+                dns_msg.generate_answers();
+                Ok(ResponseCode::NoError)
+            }
+            _ => self.resolve(&mut dns_msg, self.edns_for_upstream(&request_edns)),
+        };
+
+        dns_msg
+            .header
+            .set_header_flag(DnsHeaderFlag::Qr(QueryResponseIndicator::Response()));
+
+        // For some reason the response code is based on the op code?
+        match (dns_msg.header.get_op_code(), resolved) {
+            (OperationCode::Query(), Ok(code)) => dns_msg.set_response_code(code),
+            (OperationCode::Query(), Err(e)) => {
+                eprintln!("Failed to resolve: {}", e);
+                // Half an answer would look authoritative, so send none at all
+                dns_msg.answers.clear();
+                dns_msg.authority.clear();
+                dns_msg.extra.clear();
+                dns_msg.set_response_code(ResponseCode::ServFail);
+            }
+            _ => dns_msg.set_response_code(ResponseCode::NotImp),
+        }
+
+        println!("Flags after modification: {:016b}", dns_msg.header.flags);
+        let response_options = SerializeOptions {
+            max_size: max_response_size,
+            ..self.serialize_options.clone()
+        };
+        // Only UDP clients can retry elsewhere, TCP responses are either complete or an error
+        let serialized = match transport {
+            Transport::Udp => dns_msg.serialize_truncated(&response_options),
+            Transport::Tcp => dns_msg.serialize_with(&response_options),
+        };
+        let response = match serialized {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Failed to serialize response: {}", e);
+                DnsMessage::error_response(dns_msg.header, ResponseCode::ServFail)
+                    .serialize_as_be()
+                    .expect("header-only responses always fit")
+            }
+        };
+
+        println!("Flags after encoding: {:#?}", &response[..=11]);
+        Some(response)
+    }
+
+    /// Answers the single question in `request` from the cache if possible, and from
+    /// `resolver` otherwise.
+    fn query_cached(
+        &self,
+        resolver: &dyn Resolver,
+        request: &DnsMessage,
+    ) -> Result<DnsMessage, ResolverError> {
+        let Some(cache) = &self.cache else {
+            return resolver.resolve(request);
+        };
+        let question = &request.questions[0];
+        if let Some(response) = cache.get(question) {
+            return Ok(response);
+        }
+        let mut response = resolver.resolve(request)?;
+        cache.clamp_ttls(&mut response);
+        cache.insert(question, &response);
+        Ok(response)
+    }
+
+    /// The OPT record sent upstream, present only if the client used EDNS itself.
+    fn edns_for_upstream(&self, request_edns: &Option<Edns>) -> Option<Edns> {
+        request_edns.as_ref().map(|_| Edns::new(self.edns_udp_size))
+    }
+
+    /// Answers a single question from the zone it belongs to, or else from the resolver.
+    /// Names outside our zones are refused when there is no resolver to ask, and so are
+    /// classes other than IN that no zone of ours serves, since resolvers only handle IN.
+    fn lookup(
+        &self,
+        question: &Question,
+        request_header: &DnsHeader,
+        edns: &Option<Edns>,
+    ) -> Result<DnsMessage, ResolverError> {
+        if let Some(zone) = self
+            .zones
+            .as_ref()
+            .and_then(|zones| zones.find(&question.name, &question.resource_class))
+        {
+            let mut response = zone.answer(question);
+            // Referrals to child zones are followed instead when recursion is wanted and
+            // there is a resolver to do it
+            let referral = !response.header.get_authoritative_answer();
+            let recurse = self.resolver.is_some() && request_header.get_recursion_desired();
+            if !(referral && recurse) {
+                response
+                    .header
+                    .set_header_flag(DnsHeaderFlag::Ra(self.resolver.is_some()));
+                return Ok(response);
+            }
+        }
+        let resolver = self
+            .resolver
+            .as_ref()
+            .filter(|_| question.resource_class == ResourceClass::IN);
+        let Some(resolver) = resolver else {
+            return Ok(DnsMessage::error_response(
+                DnsHeader::builder().build(),
+                ResponseCode::Refused,
+            ));
+        };
+
+        // Duplicate dns message, but only send one question at a time. The resolver picks its
+        // own ID.
+        let mut partial_dns_msg = DnsMessage::query(question.clone());
+        partial_dns_msg.header = DnsHeader::builder()
+            .flag(DnsHeaderFlag::OpCode(request_header.get_op_code()))
+            .flag(DnsHeaderFlag::Rd(request_header.get_recursion_desired()))
+            .flag(DnsHeaderFlag::Cd(request_header.get_checking_disabled()))
+            .question_count(1)
+            .build();
+        partial_dns_msg.edns = edns.clone();
+        self.query_cached(resolver.as_ref(), &partial_dns_msg)
+    }
+
+    /// Looks up each question in turn and copies the results into `dns_msg`, along with the
+    /// AA and RA flags if every response had them. With a resolver to ask, CNAME chains are
+    /// followed out of our zones to their end. Returns the first error RCODE seen, and fails
+    /// as soon as one question can't be answered.
+    fn resolve(
+        &self,
+        dns_msg: &mut DnsMessage,
+        edns: Option<Edns>,
+    ) -> Result<ResponseCode, ResolverError> {
+        let mut response_code = ResponseCode::NoError;
+        let mut authoritative = true;
+        let mut recursion_available = true;
+
+        // break into one request per question
+        for i in 0..dns_msg.questions.len() {
+            let question = &dns_msg.questions[i];
+            let mut resolver_dns_msg = match &self.resolver {
+                // Each link of a CNAME chain is asked about separately, so each can be cached
+                Some(_) => chase::follow_chain(question, MAX_CHAIN_LENGTH, |question| {
+                    self.lookup(question, &dns_msg.header, &edns)
+                })?,
+                None => self.lookup(question, &dns_msg.header, &edns)?,
+            };
+
+            if response_code == ResponseCode::NoError {
+                response_code = resolver_dns_msg.get_response_code();
+            }
+            authoritative &= resolver_dns_msg.header.get_authoritative_answer();
+            recursion_available &= resolver_dns_msg.header.get_recursion_available();
+
+            dns_msg.header.answer_record_count += resolver_dns_msg.answers.len() as u16;
+            dns_msg.header.authority_record_count += resolver_dns_msg.authority.len() as u16;
+            dns_msg.header.additional_record_count += resolver_dns_msg.extra.len() as u16;
+            dns_msg.answers.append(&mut resolver_dns_msg.answers);
+            // Referrals, negative answers and glue live in these sections
+            dns_msg.authority.append(&mut resolver_dns_msg.authority);
+            dns_msg.extra.append(&mut resolver_dns_msg.extra);
+        }
+
+        dns_msg
+            .header
+            .set_header_flag(DnsHeaderFlag::Aa(authoritative));
+        dns_msg
+            .header
+            .set_header_flag(DnsHeaderFlag::Ra(recursion_available));
+        Ok(response_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, UdpSocket},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        dns::{
+            dns_message::ResourceRecord,
+            dns_question::{QueryType, ResourceType},
+            dns_rdata::RData,
+        },
+        resolver::forwarder::{Forwarder, ForwarderOptions},
+        zone::authority::Zone,
+    };
+
+    fn handler() -> QueryHandler {
+        QueryHandler {
+            resolver: None,
+            zones: None,
+            cache: None,
+            serialize_options: SerializeOptions::default(),
+            edns_udp_size: 1232,
+        }
+    }
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: owner.parse().unwrap(),
+            resource_class: ResourceClass::IN,
+            ttl: 3600,
+            data,
+        }
+    }
+
+    /// A handler authoritative for example.com, where www.example.com has a TXT record and
+    /// big.example.com has 50 addresses.
+    fn zone_handler() -> QueryHandler {
+        let mut records = vec![
+            record(
+                "example.com",
+                RData::SOA {
+                    mname: "ns.example.com".parse().unwrap(),
+                    rname: "hostmaster.example.com".parse().unwrap(),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86_400,
+                    minimum: 300,
+                },
+            ),
+            record("example.com", RData::NS("ns.example.com".parse().unwrap())),
+            record("www.example.com", RData::TXT(vec![b"in".to_vec()])),
+        ];
+        records.extend(
+            (0..50).map(|i| record("big.example.com", RData::A(Ipv4Addr::new(192, 0, 2, i)))),
+        );
+        let zone = Zone::new("example.com".parse().unwrap(), records).unwrap();
+        QueryHandler {
+            zones: Some(Arc::new(Catalog::new(vec![zone]))),
+            ..handler()
+        }
+    }
+
+    fn www_query(id: u16) -> DnsMessage {
+        let mut request = DnsMessage::query(Question::new(
+            "www.example.com".parse().unwrap(),
+            QueryType::Record(ResourceType::A),
+        ));
+        request.header.set_packet_identifier(id);
+        request.header.set_header_flag(DnsHeaderFlag::Rd(true));
+        request
+    }
+
+    /// Sends `request` through `handler` and parses what comes back.
+    fn exchange(handler: &QueryHandler, request: &DnsMessage, transport: Transport) -> DnsMessage {
+        let bytes = request.serialize_as_be().unwrap();
+        let response = handler.handle(&bytes, transport).unwrap();
+        DnsMessage::try_from(&response[..]).unwrap()
+    }
+
+    #[test]
+    fn does_not_echo_records_from_the_request() {
+        let planted = ResourceRecord {
+            name: "evil.org".parse().unwrap(),
+            resource_class: ResourceClass::IN,
+            ttl: 60,
+            data: RData::A(Ipv4Addr::new(6, 6, 6, 6)),
+        };
+        let mut request = www_query(42);
+        request.answers.push(planted.clone());
+        request.authority.push(planted.clone());
+        request.extra.push(planted);
+
+        let response = exchange(&handler(), &request, Transport::Udp);
+        assert_eq!(response.header.get_packet_identifier(), 42);
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.answers[0]
+            .name
+            .eq_ignore_case(&request.questions[0].name));
+        assert!(response.authority.is_empty());
+        assert!(response.extra.is_empty());
+    }
+
+    #[test]
+    fn answers_edns_clients_when_our_own_size_is_below_512() {
+        let handler = QueryHandler {
+            edns_udp_size: 256,
+            ..handler()
+        };
+        let mut request = www_query(7);
+        request.edns = Some(Edns::new(4096));
+
+        let response = exchange(&handler, &request, Transport::Udp);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn answers_servfail_when_upstream_times_out() {
+        // Bound but never read, so the forwarded query goes unanswered
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forwarder = Forwarder::new(
+            vec![upstream.local_addr().unwrap()],
+            ForwarderOptions {
+                timeout: Duration::from_millis(50),
+                retries: 1,
+                ..ForwarderOptions::default()
+            },
+        );
+        let handler = QueryHandler {
+            resolver: Some(Arc::new(forwarder)),
+            ..handler()
+        };
+
+        let response = exchange(&handler, &www_query(9), Transport::Udp);
+        assert_eq!(response.header.get_packet_identifier(), 9);
+        assert_eq!(response.get_response_code(), ResponseCode::ServFail);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn answers_formerr_to_malformed_requests() {
+        let request = www_query(0x1234).serialize_as_be().unwrap();
+        let header = &request[..12];
+        let with_name = |name: &[u8]| [header, name, &[0, 1, 0, 1]].concat();
+        let malformed = [
+            // Cut off in the middle of the name
+            request[..16].to_vec(),
+            // Cut off before the class
+            request[..request.len() - 2].to_vec(),
+            // A pointer to itself, and one pointing forwards
+            with_name(&[0xC0, 12]),
+            with_name(&[0xC0, 14, 0]),
+            // A label type reserved since RFC 6891
+            with_name(&[0b0100_0001, b'a', 0]),
+        ];
+
+        for request in malformed {
+            let response = handler().handle(&request, Transport::Udp).unwrap();
+            let response = DnsMessage::try_from(&response[..]).unwrap();
+            assert_eq!(response.header.get_packet_identifier(), 0x1234);
+            assert_eq!(response.get_response_code(), ResponseCode::FormErr);
+            assert!(response.questions.is_empty());
+        }
+    }
+
+    #[test]
+    fn ignores_requests_without_a_whole_header() {
+        assert!(handler().handle(&[], Transport::Udp).is_none());
+        assert!(handler().handle(&[0x12, 0x34, 1], Transport::Udp).is_none());
+        assert!(handler().handle(&[0; 11], Transport::Tcp).is_none());
+    }
+
+    #[test]
+    fn answers_badvers_to_unknown_edns_versions() {
+        let mut request = www_query(11);
+        let mut edns = Edns::new(4096);
+        edns.version = 1;
+        request.edns = Some(edns);
+
+        let response = exchange(&handler(), &request, Transport::Udp);
+        assert_eq!(response.header.get_packet_identifier(), 11);
+        assert_eq!(response.get_response_code(), ResponseCode::BADVERS);
+        assert!(response.answers.is_empty());
+        let edns = response.edns.unwrap();
+        assert_eq!(edns.version, EDNS_VERSION);
+        assert_eq!(edns.udp_payload_size, 1232);
+    }
+
+    #[test]
+    fn echoes_opt_with_our_own_size_only_to_edns_clients() {
+        let mut request = www_query(12);
+        let mut edns = Edns::new(4096);
+        edns.dnssec_ok = true;
+        request.edns = Some(edns);
+
+        let response = exchange(&handler(), &request, Transport::Udp);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        let edns = response.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(edns.dnssec_ok);
+
+        let response = exchange(&handler(), &www_query(13), Transport::Udp);
+        assert!(response.edns.is_none());
+    }
+
+    #[test]
+    fn truncates_to_the_payload_size_the_client_offers() {
+        let mut request = DnsMessage::query(Question::new(
+            "big.example.com".parse().unwrap(),
+            QueryType::Record(ResourceType::A),
+        ));
+
+        // The 50 addresses take about 850 bytes, too many for plain UDP
+        let response = exchange(&zone_handler(), &request, Transport::Udp);
+        assert!(response.header.get_truncation());
+        assert!(response.answers.is_empty());
+
+        request.edns = Some(Edns::new(1000));
+        let response = exchange(&zone_handler(), &request, Transport::Udp);
+        assert!(!response.header.get_truncation());
+        assert_eq!(response.answers.len(), 50);
+
+        // Clients offering more than our own size still get at most ours
+        let handler = QueryHandler {
+            edns_udp_size: 800,
+            ..zone_handler()
+        };
+        request.edns = Some(Edns::new(4096));
+        let response = exchange(&handler, &request, Transport::Udp);
+        assert!(response.header.get_truncation());
+
+        // Over TCP nothing is cut
+        let response = exchange(&handler, &request, Transport::Tcp);
+        assert!(!response.header.get_truncation());
+        assert_eq!(response.answers.len(), 50);
+    }
+
+    #[test]
+    fn refuses_classes_no_zone_serves() {
+        let mut request = DnsMessage::query(Question {
+            name: "www.example.com".parse().unwrap(),
+            query_type: QueryType::Record(ResourceType::TXT),
+            resource_class: ResourceClass::CH,
+        });
+        request.header.set_header_flag(DnsHeaderFlag::Rd(true));
+
+        let response = exchange(&zone_handler(), &request, Transport::Udp);
+        assert_eq!(response.get_response_code(), ResponseCode::Refused);
+        assert!(response.answers.is_empty());
+        assert!(!response.header.get_authoritative_answer());
+
+        // Not even passed on to a resolver, which would wait for this one to time out
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forwarder = Forwarder::new(
+            vec![upstream.local_addr().unwrap()],
+            ForwarderOptions {
+                timeout: Duration::from_millis(50),
+                retries: 0,
+                ..ForwarderOptions::default()
+            },
+        );
+        let handler = QueryHandler {
+            resolver: Some(Arc::new(forwarder)),
+            ..zone_handler()
+        };
+        let response = exchange(&handler, &request, Transport::Udp);
+        assert_eq!(response.get_response_code(), ResponseCode::Refused);
+        assert!(response.answers.is_empty());
+
+        request.questions[0].resource_class = ResourceClass::IN;
+        let response = exchange(&handler, &request, Transport::Udp);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers[0].data, RData::TXT(vec![b"in".to_vec()]));
+        assert!(response.header.get_authoritative_answer());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod handler;
+pub mod pool;
+pub mod tcp;
+pub mod udp;
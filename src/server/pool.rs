@@ -0,0 +1,155 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of threads working through a bounded queue of jobs, so a slow request only
+/// ties up one worker instead of the whole server.
+pub struct WorkerPool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Starts `size` workers sharing a queue that holds up to `queue_capacity` waiting jobs.
+    pub fn new(size: usize, queue_capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size.max(1))
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("worker-{}", id))
+                    .spawn(move || Self::work(&receiver))
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Queues `job` for the next free worker. Returns false, dropping the job, when the queue
+    /// is full; for UDP that is no worse than a lost datagram, which clients already retry.
+    pub fn try_execute<F>(&self, job: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().expect("sender lives until drop");
+        match sender.try_send(Box::new(job)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // The lock is released as soon as a job is taken, not while it runs
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            let Ok(job) = job else {
+                // The pool was dropped
+                return;
+            };
+            // One bad request shouldn't take a worker down with it
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                eprintln!(
+                    "Worker {:?} recovered from a panic",
+                    thread::current().name()
+                );
+            }
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Lets the workers finish what is queued, then waits for them to exit.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn runs_jobs_on_the_workers() {
+        let pool = WorkerPool::new(4, 16);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..8 {
+            let sender = sender.clone();
+            assert!(pool.try_execute(move || sender.send(i).unwrap()));
+        }
+
+        let mut done: Vec<i32> = (0..8)
+            .map(|_| receiver.recv_timeout(WAIT).unwrap())
+            .collect();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn refuses_jobs_once_the_queue_is_full() {
+        let pool = WorkerPool::new(1, 1);
+        let (started, has_started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        assert!(pool.try_execute(move || {
+            started.send(()).unwrap();
+            released.recv_timeout(WAIT).unwrap();
+        }));
+        has_started.recv_timeout(WAIT).unwrap();
+
+        // The only worker is busy, so one job waits and the next has nowhere to go
+        assert!(pool.try_execute(|| {}));
+        assert!(!pool.try_execute(|| {}));
+
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn keeps_working_after_a_job_panics() {
+        let pool = WorkerPool::new(1, 4);
+        let (sender, receiver) = mpsc::channel();
+        assert!(pool.try_execute(|| panic!("bad request")));
+        assert!(pool.try_execute(move || sender.send(()).unwrap()));
+
+        assert!(receiver.recv_timeout(WAIT).is_ok());
+    }
+
+    #[test]
+    fn finishes_queued_jobs_when_dropped() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let pool = WorkerPool::new(1, 8);
+        for _ in 0..8 {
+            let finished = Arc::clone(&finished);
+            assert!(pool.try_execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                finished.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+
+        drop(pool);
+        assert_eq!(finished.load(Ordering::SeqCst), 8);
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    io::{self, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::dns::buffer_packets::BytePacketBuffer;
+
+use super::handler::{QueryHandler, Transport};
+
+#[derive(Debug, Clone)]
+pub struct TcpOptions {
+    /// How long a connection may sit without sending a complete query before it is closed.
+    pub idle_timeout: Duration,
+    /// Connections beyond this many are closed right after being accepted.
+    pub max_connections: usize,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        // RFC 7766 recommends timeouts in the order of seconds
+        TcpOptions {
+            idle_timeout: Duration::from_secs(10),
+            max_connections: 64,
+        }
+    }
+}
+
+/// Accepts connections on `listener` and answers each on its own thread. Every query is
+/// framed by a two byte length prefix (RFC 1035 section 4.2.2), and a client may send
+/// several queries on one connection without waiting for the answers.
+pub fn serve(listener: TcpListener, handler: Arc<QueryHandler>, options: TcpOptions) {
+    let open_connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+
+        if open_connections.fetch_add(1, Ordering::SeqCst) >= options.max_connections {
+            open_connections.fetch_sub(1, Ordering::SeqCst);
+            eprintln!(
+                "Refusing connection from {}: too many open connections",
+                peer
+            );
+            continue;
+        }
+        println!("Accepted connection from {}", peer);
+
+        let handler = Arc::clone(&handler);
+        let open_connections = Arc::clone(&open_connections);
+        let idle_timeout = options.idle_timeout;
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &handler, idle_timeout) {
+                eprintln!("Connection from {} failed: {}", peer, e);
+            }
+            open_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Answers queries from `stream` in the order they arrive until the client closes the
+/// connection or stays quiet for longer than `idle_timeout`.
+fn handle_connection(
+    stream: TcpStream,
+    handler: &QueryHandler,
+    idle_timeout: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(idle_timeout))?;
+    stream.set_write_timeout(Some(idle_timeout))?;
+    let mut writer = stream.try_clone()?;
+    // Pipelined queries tend to arrive in one segment, so buffer the reads
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut length = [0; 2];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            // Closing between queries is the normal way to end a connection
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) if is_timeout(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = u16::from_be_bytes(length) as usize;
+
+        let mut request = BytePacketBuffer::with_max_size(length);
+        reader.read_exact(&mut request.buf)?;
+        request.position = length;
+
+        // Without a header the framing can't be trusted any more
+        let Some(response) = handler.handle(request.filled(), Transport::Tcp) else {
+            eprintln!("Closing connection after an unanswerable request");
+            return Ok(());
+        };
+
+        let mut framed = Vec::with_capacity(2 + response.len());
+        framed.extend_from_slice(&(response.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&response);
+        writer.write_all(&framed)?;
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::dns::{
+        dns_header::DnsHeaderFlag,
+        dns_message::{DnsMessage, SerializeOptions},
+        dns_question::{QueryType, Question, ResourceType},
+    };
+
+    /// Serves canned answers on a loopback port for the rest of the test run.
+    fn start(options: TcpOptions) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handler = Arc::new(QueryHandler {
+            resolver: None,
+            zones: None,
+            cache: None,
+            serialize_options: SerializeOptions::default(),
+            edns_udp_size: 1232,
+        });
+        thread::spawn(move || serve(listener, handler, options));
+        address
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    fn framed_query(id: u16) -> Vec<u8> {
+        let mut query = DnsMessage::query(Question::new(
+            "example.com".parse().unwrap(),
+            QueryType::Record(ResourceType::A),
+        ));
+        query.header.set_packet_identifier(id);
+        query.header.set_header_flag(DnsHeaderFlag::Rd(true));
+        let query = query.serialize_as_be().unwrap();
+        [&(query.len() as u16).to_be_bytes()[..], &query].concat()
+    }
+
+    fn receive(stream: &mut TcpStream) -> DnsMessage {
+        let mut length = [0; 2];
+        stream.read_exact(&mut length).unwrap();
+        let mut response = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut response).unwrap();
+        DnsMessage::try_from(&response[..]).unwrap()
+    }
+
+    /// Whether the server hung up, rather than the read timing out or data arriving.
+    fn is_closed(stream: &mut TcpStream) -> bool {
+        match stream.read(&mut [0; 1]) {
+            Ok(read) => read == 0,
+            Err(e) => !is_timeout(&e),
+        }
+    }
+
+    #[test]
+    fn answers_length_prefixed_queries() {
+        let mut stream = connect(start(TcpOptions::default()));
+        // Split inside the length prefix, as a slow client might
+        let query = framed_query(1);
+        stream.write_all(&query[..1]).unwrap();
+        stream.write_all(&query[1..]).unwrap();
+
+        let response = receive(&mut stream);
+        assert_eq!(response.header.get_packet_identifier(), 1);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn answers_pipelined_queries_in_order() {
+        let mut stream = connect(start(TcpOptions::default()));
+        let queries: Vec<u8> = (1..=3).flat_map(framed_query).collect();
+        stream.write_all(&queries).unwrap();
+
+        for id in 1..=3 {
+            assert_eq!(receive(&mut stream).header.get_packet_identifier(), id);
+        }
+    }
+
+    #[test]
+    fn closes_idle_connections() {
+        let mut stream = connect(start(TcpOptions {
+            idle_timeout: Duration::from_millis(100),
+            ..TcpOptions::default()
+        }));
+        stream.write_all(&framed_query(1)).unwrap();
+        receive(&mut stream);
+
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn closes_connections_over_the_limit() {
+        let address = start(TcpOptions {
+            max_connections: 1,
+            ..TcpOptions::default()
+        });
+        let mut first = connect(address);
+        // Answered, so the server has certainly counted it
+        first.write_all(&framed_query(1)).unwrap();
+        receive(&mut first);
+
+        let mut second = connect(address);
+        assert!(is_closed(&mut second));
+
+        first.write_all(&framed_query(2)).unwrap();
+        assert_eq!(receive(&mut first).header.get_packet_identifier(), 2);
+    }
+}
@@ -0,0 +1,45 @@
+use std::{net::UdpSocket, sync::Arc};
+
+use crate::dns::buffer_packets::{BytePacketBuffer, MAX_MESSAGE_SIZE};
+
+use super::{
+    handler::{QueryHandler, Transport},
+    pool::WorkerPool,
+};
+
+/// Receives datagrams on `socket` and answers each on `pool`, until receiving fails.
+pub fn serve(socket: UdpSocket, handler: Arc<QueryHandler>, pool: &WorkerPool) {
+    let socket = Arc::new(socket);
+    // Requests larger than the classic 512 bytes are still read in full
+    let mut packet = BytePacketBuffer::with_max_size(MAX_MESSAGE_SIZE);
+
+    loop {
+        match socket.recv_from(&mut packet.buf) {
+            Ok((size, source)) => {
+                println!("Received {} bytes from {}", size, source);
+                packet.position = size;
+
+                // The receive buffer is reused right away, so each request gets its own copy
+                let request = packet.filled().to_vec();
+                let socket = Arc::clone(&socket);
+                let handler = Arc::clone(&handler);
+                let queued = pool.try_execute(move || {
+                    let Some(response) = handler.handle(&request, Transport::Udp) else {
+                        eprintln!("Dropping unanswerable request from {}", source);
+                        return;
+                    };
+                    if let Err(e) = socket.send_to(&response, source) {
+                        eprintln!("Failed to send response to {}: {}", source, e);
+                    }
+                });
+                if !queued {
+                    eprintln!("Dropping request from {}: all workers are busy", source);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error receiving data: {}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::dns::{
+    dns_header::{DnsHeaderFlag, ResponseCode},
+    dns_message::{DnsMessage, ResourceRecord},
+    dns_question::{DomainName, QueryType, Question, ResourceClass, ResourceType},
+    dns_rdata::RData,
+};
+
+use super::{master_file, zone_error::ZoneError};
+
+/// A zone loaded from a master file, answered authoritatively for every name at or below
+/// its origin.
+#[derive(Debug)]
+pub struct Zone {
+    pub origin: DomainName,
+    /// The class of the SOA record; questions in other classes aren't the zone's to answer.
+    pub resource_class: ResourceClass,
+    /// Records by lowercased owner name.
+    records: HashMap<String, Vec<ResourceRecord>>,
+    /// Every name that exists in the zone: the owners, and the empty non-terminals between
+    /// them and the origin, which have no records but do have names below them.
+    names: HashSet<String>,
+}
+
+impl Zone {
+    /// Builds the zone at `origin` from `records`. There must be a SOA record at the origin,
+    /// and records outside the zone or in a class other than the SOA's are dropped.
+    pub fn new(origin: DomainName, records: Vec<ResourceRecord>) -> Result<Self, ZoneError> {
+        let resource_class = records
+            .iter()
+            .find(|record| {
+                record.resource_type() == ResourceType::SOA && record.name.eq_ignore_case(&origin)
+            })
+            .map(|soa| soa.resource_class.clone())
+            .ok_or_else(|| ZoneError::MissingSoa(origin.to_string()))?;
+        let mut zone = Zone {
+            origin,
+            resource_class,
+            records: HashMap::new(),
+            names: HashSet::new(),
+        };
+        for record in records {
+            if !record.name.is_subdomain_of(&zone.origin) {
+                eprintln!("Ignoring {} outside of zone {}", record, zone.origin);
+                continue;
+            }
+            if record.resource_class != zone.resource_class {
+                eprintln!(
+                    "Ignoring {} in class {} in zone {} of class {}",
+                    record, record.resource_class, zone.origin, zone.resource_class
+                );
+                continue;
+            }
+            let mut name = record.name.clone();
+            while zone.names.insert(name.to_lowercase_key())
+                && name.content.len() > zone.origin.content.len()
+            {
+                name.content.remove(0);
+            }
+            zone.records
+                .entry(record.name.to_lowercase_key())
+                .or_default()
+                .push(record);
+        }
+        Ok(zone)
+    }
+
+    /// Loads the zone at `origin` from the master file at `path`.
+    pub fn load(origin: DomainName, path: &Path) -> Result<Self, ZoneError> {
+        let records = master_file::load(path, &origin)?;
+        Zone::new(origin, records)
+    }
+
+    /// The records at exactly `name`, if it owns any.
+    fn records_at(&self, name: &DomainName) -> &[ResourceRecord] {
+        self.records
+            .get(&name.to_lowercase_key())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn soa(&self) -> Option<&ResourceRecord> {
+        self.records_at(&self.origin)
+            .iter()
+            .find(|record| record.resource_type() == ResourceType::SOA)
+    }
+
+    /// The SOA record that goes with negative answers, whose TTL is how long they may be
+    /// cached: the smaller of its own TTL and its MINIMUM field (RFC 2308 section 3).
+    fn negative_soa(&self) -> ResourceRecord {
+        let mut soa = self.soa().expect("zones always have a SOA").clone();
+        if let RData::SOA { minimum, .. } = soa.data {
+            soa.ttl = soa.ttl.min(minimum);
+        }
+        soa
+    }
+
+    /// Answers `question`, whose name and class must be the zone's, following CNAME and DNAME
+    /// records as far as the zone goes (RFC 1034 section 4.3.2). Names the zone doesn't have
+    /// may still match a wildcard; otherwise they get NXDOMAIN, and types a name doesn't have
+    /// NODATA, both with the SOA in authority. Names in delegated child zones get a referral.
+    pub fn answer(&self, question: &Question) -> DnsMessage {
+        let mut response = DnsMessage::query(question.clone());
+        response.header.set_header_flag(DnsHeaderFlag::Aa(true));
+
+        let mut name = question.name.clone();
+        let mut seen = vec![name.to_lowercase_key()];
+        loop {
+            // Names at or below a zone cut are the child zone's to answer for
+            if let Some(cut) = self.zone_cut(&name) {
+                self.add_referral(&mut response, &cut);
+                break;
+            }
+
+            // A DNAME above the name moves it to another part of the tree (RFC 6672)
+            if let Some((dname, target)) = self.dname_above(&name) {
+                response.answers.push(dname.clone());
+                response.answers.push(ResourceRecord {
+                    name: name.clone(),
+                    resource_class: dname.resource_class.clone(),
+                    ttl: dname.ttl,
+                    data: RData::CNAME(target.clone()),
+                });
+                if !self.follows(&target, &mut seen) {
+                    break;
+                }
+                name = target;
+                continue;
+            }
+
+            let Some(records) = self.node(&name) else {
+                response.set_response_code(ResponseCode::NXDomain);
+                response.authority.push(self.negative_soa());
+                break;
+            };
+
+            // Empty non-terminals have nothing to match, and end up as NODATA below
+            let matching: Vec<&ResourceRecord> = records
+                .iter()
+                .filter(|record| matches_query_type(&question.query_type, record))
+                .collect();
+            if !matching.is_empty() {
+                response.answers.extend(matching.into_iter().cloned());
+                break;
+            }
+
+            let cname = records.iter().find_map(|record| match &record.data {
+                RData::CNAME(target) => Some((record, target)),
+                _ => None,
+            });
+            match cname {
+                Some((cname, target)) => {
+                    response.answers.push(cname.clone());
+                    if !self.follows(target, &mut seen) {
+                        break;
+                    }
+                    name = target.clone();
+                }
+                None => {
+                    response.authority.push(self.negative_soa());
+                    break;
+                }
+            }
+        }
+
+        self.add_additional(&mut response);
+        response
+    }
+
+    /// The highest delegation between the origin and `name`: the first name on the way down
+    /// that has NS records without being the apex, which may be `name` itself.
+    fn zone_cut(&self, name: &DomainName) -> Option<DomainName> {
+        (0..name.content.len() - self.origin.content.len())
+            .rev()
+            .map(|prefix| DomainName {
+                content: name.content[prefix..].to_vec(),
+            })
+            .find(|ancestor| {
+                self.records_at(ancestor)
+                    .iter()
+                    .any(|record| record.resource_type() == ResourceType::NS)
+            })
+    }
+
+    /// Turns `response` into a referral to the child zone at `cut`: its NS records in
+    /// authority, and the addresses we have for those servers as glue in additional. Only
+    /// answers already given (e.g. a CNAME leading here) are authoritative.
+    fn add_referral(&self, response: &mut DnsMessage, cut: &DomainName) {
+        let name_servers: Vec<&ResourceRecord> = self
+            .records_at(cut)
+            .iter()
+            .filter(|record| record.resource_type() == ResourceType::NS)
+            .collect();
+        for name_server in &name_servers {
+            let RData::NS(target) = &name_server.data else {
+                continue;
+            };
+            let already_added = response
+                .extra
+                .iter()
+                .any(|record| record.name.eq_ignore_case(target));
+            if already_added || !target.is_subdomain_of(&self.origin) {
+                continue;
+            }
+            // Glue below the cut isn't our data to answer with, but it is ours to hand out
+            let glue = self
+                .records_at(target)
+                .iter()
+                .filter(|record| matches!(record.data, RData::A(_) | RData::AAAA(_)))
+                .cloned();
+            response.extra.extend(glue);
+        }
+        response.authority.extend(name_servers.into_iter().cloned());
+        response
+            .header
+            .set_header_flag(DnsHeaderFlag::Aa(!response.answers.is_empty()));
+    }
+
+    /// The records of `name`, which are those it owns if it exists, and otherwise those
+    /// synthesized from the wildcard at its closest encloser with the owner replaced by
+    /// `name` (RFC 4592 section 3.3.1). `None` means the name doesn't exist at all.
+    fn node(&self, name: &DomainName) -> Option<Vec<ResourceRecord>> {
+        if self.names.contains(&name.to_lowercase_key()) {
+            return Some(self.records_at(name).to_vec());
+        }
+
+        // The closest encloser is the nearest ancestor that exists, even as an empty
+        // non-terminal; only a wildcard right below it can match
+        let closest_encloser = (1..=name.content.len() - self.origin.content.len())
+            .map(|prefix| DomainName {
+                content: name.content[prefix..].to_vec(),
+            })
+            .find(|ancestor| self.names.contains(&ancestor.to_lowercase_key()))?;
+        let mut wildcard = closest_encloser;
+        wildcard.content.insert(0, b"*".to_vec());
+
+        // A wildcard with only names below it still matches, with no data of its own
+        if !self.names.contains(&wildcard.to_lowercase_key()) {
+            return None;
+        }
+        let synthesized = self
+            .records_at(&wildcard)
+            .iter()
+            .map(|record| ResourceRecord {
+                name: name.clone(),
+                ..record.clone()
+            })
+            .collect();
+        Some(synthesized)
+    }
+
+    /// Whether a chain reaching `target` goes on inside this zone. Chains leaving the zone
+    /// are left to whoever asked, and ones coming back to a name end there.
+    fn follows(&self, target: &DomainName, seen: &mut Vec<String>) -> bool {
+        let target_key = target.to_lowercase_key();
+        if !target.is_subdomain_of(&self.origin) || seen.contains(&target_key) {
+            return false;
+        }
+        seen.push(target_key);
+        true
+    }
+
+    /// The closest DNAME record strictly above `name` within the zone, with the name it
+    /// turns `name` into.
+    fn dname_above(&self, name: &DomainName) -> Option<(&ResourceRecord, DomainName)> {
+        (1..=name.content.len() - self.origin.content.len()).find_map(|prefix| {
+            let owner = DomainName {
+                content: name.content[prefix..].to_vec(),
+            };
+            self.records_at(&owner)
+                .iter()
+                .find_map(|record| match &record.data {
+                    RData::DNAME(target) => {
+                        let mut content = name.content[..prefix].to_vec();
+                        content.extend(target.content.iter().cloned());
+                        Some((record, DomainName { content }))
+                    }
+                    _ => None,
+                })
+        })
+    }
+
+    /// Adds the addresses the zone has for names in NS, MX and SRV answers, saving the
+    /// client a lookup (RFC 1035 section 3.3).
+    fn add_additional(&self, response: &mut DnsMessage) {
+        let targets: Vec<DomainName> = response
+            .answers
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::NS(name) => Some(name.clone()),
+                RData::MX { exchange, .. } => Some(exchange.clone()),
+                RData::SRV { target, .. } => Some(target.clone()),
+                _ => None,
+            })
+            .collect();
+        for target in targets {
+            let already_added = response
+                .extra
+                .iter()
+                .any(|record| record.name.eq_ignore_case(&target));
+            if already_added || !target.is_subdomain_of(&self.origin) {
+                continue;
+            }
+            let addresses = self
+                .records_at(&target)
+                .iter()
+                .filter(|record| matches!(record.data, RData::A(_) | RData::AAAA(_)))
+                .cloned();
+            response.extra.extend(addresses);
+        }
+    }
+}
+
+/// Whether `record` answers a question of `query_type`.
+fn matches_query_type(query_type: &QueryType, record: &ResourceRecord) -> bool {
+    match query_type {
+        QueryType::Record(resource_type) => record.resource_type() == *resource_type,
+        QueryType::ANY => true,
+        QueryType::MAILB => matches!(
+            record.resource_type(),
+            ResourceType::MB | ResourceType::MG | ResourceType::MR
+        ),
+        QueryType::MAILA => matches!(record.resource_type(), ResourceType::MD | ResourceType::MF),
+        // Zone transfers aren't served
+        QueryType::AXFR | QueryType::IXFR => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn record(owner: &str, data: RData) -> ResourceRecord {
+        ResourceRecord {
+            name: owner.parse().unwrap(),
+            resource_class: ResourceClass::IN,
+            ttl: 3600,
+            data,
+        }
+    }
+
+    /// example.com with a wildcard at the apex, and b.example.com existing only as the
+    /// parent of a.b.example.com.
+    fn records() -> Vec<ResourceRecord> {
+        vec![
+            record(
+                "example.com",
+                RData::SOA {
+                    mname: "ns.example.com".parse().unwrap(),
+                    rname: "hostmaster.example.com".parse().unwrap(),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86_400,
+                    minimum: 300,
+                },
+            ),
+            record("example.com", RData::NS("ns.example.com".parse().unwrap())),
+            record("ns.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+            record("*.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 10))),
+            record("a.b.example.com", RData::A(Ipv4Addr::new(192, 0, 2, 20))),
+        ]
+    }
+
+    fn zone() -> Zone {
+        Zone::new("example.com".parse().unwrap(), records()).unwrap()
+    }
+
+    /// The same zone delegating sub.example.com to a server inside it, with glue, and to
+    /// one elsewhere, and with a CNAME pointing into the child zone.
+    fn delegating_zone() -> Zone {
+        let mut records = records();
+        records.extend([
+            record(
+                "sub.example.com",
+                RData::NS("ns1.sub.example.com".parse().unwrap()),
+            ),
+            record(
+                "sub.example.com",
+                RData::NS("ns.example.org".parse().unwrap()),
+            ),
+            record(
+                "ns1.sub.example.com",
+                RData::A(Ipv4Addr::new(192, 0, 2, 30)),
+            ),
+            record(
+                "alias.example.com",
+                RData::CNAME("www.sub.example.com".parse().unwrap()),
+            ),
+        ]);
+        Zone::new("example.com".parse().unwrap(), records).unwrap()
+    }
+
+    fn ask(zone: &Zone, owner: &str, resource_type: ResourceType) -> DnsMessage {
+        zone.answer(&Question::new(
+            owner.parse().unwrap(),
+            QueryType::Record(resource_type),
+        ))
+    }
+
+    fn is_negative(response: &DnsMessage) -> bool {
+        response.answers.is_empty()
+            && response.authority.len() == 1
+            && response.authority[0].resource_type() == ResourceType::SOA
+    }
+
+    #[test]
+    fn synthesizes_answers_from_the_wildcard_under_the_name() {
+        let response = ask(&zone(), "x.example.com", ResourceType::A);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert!(response.header.get_authoritative_answer());
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.answers[0]
+            .name
+            .eq_ignore_case(&"x.example.com".parse().unwrap()));
+        assert_eq!(
+            response.answers[0].data,
+            RData::A(Ipv4Addr::new(192, 0, 2, 10))
+        );
+    }
+
+    #[test]
+    fn answers_nodata_for_wildcard_matches_without_the_type() {
+        let response = ask(&zone(), "x.example.com", ResourceType::MX);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert!(is_negative(&response));
+    }
+
+    #[test]
+    fn does_not_apply_the_wildcard_to_empty_non_terminals() {
+        let response = ask(&zone(), "b.example.com", ResourceType::A);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert!(is_negative(&response));
+    }
+
+    #[test]
+    fn answers_nxdomain_below_an_empty_non_terminal() {
+        // The closest encloser is b.example.com, which has no wildcard of its own
+        let response = ask(&zone(), "x.b.example.com", ResourceType::A);
+        assert_eq!(response.get_response_code(), ResponseCode::NXDomain);
+        assert!(is_negative(&response));
+        assert_eq!(response.authority[0].ttl, 300);
+    }
+
+    #[test]
+    fn prefers_existing_names_over_the_wildcard() {
+        let zone = zone();
+        let response = ask(&zone, "a.b.example.com", ResourceType::A);
+        assert_eq!(
+            response.answers[0].data,
+            RData::A(Ipv4Addr::new(192, 0, 2, 20))
+        );
+
+        let response = ask(&zone, "ns.example.com", ResourceType::TXT);
+        assert_eq!(response.get_response_code(), ResponseCode::NoError);
+        assert!(is_negative(&response));
+    }
+
+    fn is_referral_to_sub(response: &DnsMessage) -> bool {
+        response.get_response_code() == ResponseCode::NoError
+            && response.authority.len() == 2
+            && response.authority.iter().all(|record| {
+                record.resource_type() == ResourceType::NS
+                    && record.name.eq_ignore_case(&"sub.example.com".parse().unwrap())
+            })
+            // Only the server inside our zone comes with glue
+            && response.extra.len() == 1
+            && response.extra[0].name.eq_ignore_case(&"ns1.sub.example.com".parse().unwrap())
+            && response.extra[0].data == RData::A(Ipv4Addr::new(192, 0, 2, 30))
+    }
+
+    #[test]
+    fn refers_names_below_a_zone_cut() {
+        let response = ask(&delegating_zone(), "www.sub.example.com", ResourceType::A);
+        assert!(is_referral_to_sub(&response));
+        assert!(response.answers.is_empty());
+        assert!(!response.header.get_authoritative_answer());
+    }
+
+    #[test]
+    fn refers_questions_about_the_zone_cut_itself() {
+        let response = ask(&delegating_zone(), "sub.example.com", ResourceType::NS);
+        assert!(is_referral_to_sub(&response));
+        assert!(response.answers.is_empty());
+        assert!(!response.header.get_authoritative_answer());
+    }
+
+    #[test]
+    fn answers_authoritatively_for_the_way_into_a_delegation() {
+        let response = ask(&delegating_zone(), "alias.example.com", ResourceType::A);
+        assert!(is_referral_to_sub(&response));
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].data,
+            RData::CNAME("www.sub.example.com".parse().unwrap())
+        );
+        assert!(response.header.get_authoritative_answer());
+    }
+}
@@ -0,0 +1,84 @@
+use super::authority::Zone;
+use crate::dns::dns_question::{DomainName, ResourceClass};
+
+/// The zones this server is authoritative for.
+#[derive(Debug)]
+pub struct Catalog {
+    zones: Vec<Zone>,
+}
+
+impl Catalog {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Catalog { zones }
+    }
+
+    /// The zone `name` belongs to in `resource_class`: of the zones containing it, the one
+    /// with the longest origin. QCLASS ANY matches zones of every class.
+    pub fn find(&self, name: &DomainName, resource_class: &ResourceClass) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| {
+                *resource_class == ResourceClass::QClassAny
+                    || zone.resource_class == *resource_class
+            })
+            .filter(|zone| name.is_subdomain_of(&zone.origin))
+            .max_by_key(|zone| zone.origin.content.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{dns_message::ResourceRecord, dns_rdata::RData};
+
+    fn zone(origin: &str, resource_class: ResourceClass) -> Zone {
+        let soa = ResourceRecord {
+            name: origin.parse().unwrap(),
+            resource_class,
+            ttl: 3600,
+            data: RData::SOA {
+                mname: "ns.example.com".parse().unwrap(),
+                rname: "hostmaster.example.com".parse().unwrap(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86_400,
+                minimum: 300,
+            },
+        };
+        Zone::new(origin.parse().unwrap(), vec![soa]).unwrap()
+    }
+
+    #[test]
+    fn finds_the_closest_zone_of_the_class_asked() {
+        let catalog = Catalog::new(vec![
+            zone("example.com", ResourceClass::IN),
+            zone("sub.example.com", ResourceClass::IN),
+            zone("bind", ResourceClass::CH),
+        ]);
+        let find = |name: &str, class: ResourceClass| {
+            catalog
+                .find(&name.parse().unwrap(), &class)
+                .map(|zone| zone.origin.to_string())
+        };
+
+        assert_eq!(
+            find("www.sub.example.com", ResourceClass::IN).as_deref(),
+            Some("sub.example.com.")
+        );
+        assert_eq!(
+            find("www.example.com", ResourceClass::IN).as_deref(),
+            Some("example.com.")
+        );
+        assert_eq!(find("www.example.com", ResourceClass::CH), None);
+        assert_eq!(find("version.bind", ResourceClass::IN), None);
+        assert_eq!(
+            find("version.bind", ResourceClass::CH).as_deref(),
+            Some("bind.")
+        );
+        assert_eq!(
+            find("www.example.com", ResourceClass::QClassAny).as_deref(),
+            Some("example.com.")
+        );
+    }
+}
@@ -0,0 +1,789 @@
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::dns::{
+    dns_message::ResourceRecord,
+    dns_question::{unescape, DomainName, ResourceClass, ResourceType},
+    dns_rdata::RData,
+};
+
+use super::zone_error::ZoneError;
+
+/// Files included from included files, counting the first one.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Reads the master file at `path` (RFC 1035 section 5) for a zone whose names are relative
+/// to `origin`, following $INCLUDE directives, and returns its records in file order.
+pub fn load(path: &Path, origin: &DomainName) -> Result<Vec<ResourceRecord>, ZoneError> {
+    let mut records = vec![];
+    load_into(path, origin, None, 1, &mut records)?;
+    Ok(records)
+}
+
+fn load_into(
+    path: &Path,
+    origin: &DomainName,
+    default_ttl: Option<u32>,
+    depth: usize,
+    records: &mut Vec<ResourceRecord>,
+) -> Result<(), ZoneError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(ZoneError::IncludeTooDeep(MAX_INCLUDE_DEPTH));
+    }
+    let text = fs::read_to_string(path).map_err(|source| ZoneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut parser = Parser {
+        path,
+        depth,
+        origin: origin.clone(),
+        default_ttl,
+        last_ttl: None,
+        last_owner: None,
+        last_class: ResourceClass::IN,
+    };
+    let entries = tokenize(&text).map_err(|(line, message)| parser.error(line, message))?;
+    for entry in entries {
+        parser.entry(&entry, records)?;
+    }
+    Ok(())
+}
+
+/// Parses a name as written in a master file: `@` for the origin, absolute when it ends in
+/// a dot and relative to `origin` otherwise, with `\.` and `\DDD` escapes inside labels.
+pub fn parse_name(text: &str, origin: &DomainName) -> Result<DomainName, String> {
+    if text == "@" {
+        return Ok(origin.clone());
+    }
+    let (mut name, absolute) = DomainName::parse_labels(text)?;
+    if !absolute {
+        name.content.extend(origin.content.iter().cloned());
+    }
+    if name.encoded_length() > DomainName::MAX_ENCODED_LENGTH {
+        return Err(format!("name {} is longer than 255 bytes", name));
+    }
+    Ok(name)
+}
+
+/// Parses a TTL given in seconds or with units, e.g. `3600`, `1h` or `1d12h`.
+pub fn parse_ttl(text: &str) -> Result<u32, String> {
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+
+    let invalid = || format!("invalid TTL {:?}", text);
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit: u32 = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u32 = number.parse().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    // Every number needs a unit once units are used
+    match number.is_empty() {
+        true => Ok(total),
+        false => Err(invalid()),
+    }
+}
+
+/////////////////////////////////////////////////////
+// TOKENS
+/////////////////////////////////////////////////////
+/// A single field of an entry, with escapes still in place.
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// One directive or record. Parentheses let an entry span several lines.
+#[derive(Debug)]
+struct Entry {
+    /// Line the entry starts on.
+    line: usize,
+    /// The entry starts with whitespace, so it belongs to the previous owner.
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// Splits a master file into entries, dropping comments and parentheses. Errors carry the
+/// line they were found on.
+fn tokenize(text: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut entries = vec![];
+    let mut tokens = vec![];
+    let mut entry_line = 1;
+    let mut entry_blank_owner = false;
+    let mut line = 1;
+    let mut line_indented = matches!(chars.first(), Some(' ' | '\t'));
+    let mut parentheses = 0;
+
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\n' => {
+                line += 1;
+                line_indented = matches!(chars.get(i + 1), Some(' ' | '\t'));
+                if parentheses == 0 && !tokens.is_empty() {
+                    entries.push(Entry {
+                        line: entry_line,
+                        blank_owner: entry_blank_owner,
+                        tokens: std::mem::take(&mut tokens),
+                    });
+                }
+                i += 1;
+                continue;
+            }
+            ';' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            ' ' | '\t' | '\r' => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                parentheses += 1;
+                i += 1;
+                continue;
+            }
+            ')' => {
+                if parentheses == 0 {
+                    return Err((
+                        line,
+                        "closing parenthesis without an opening one".to_string(),
+                    ));
+                }
+                parentheses -= 1;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        if tokens.is_empty() {
+            entry_line = line;
+            entry_blank_owner = line_indented;
+        }
+        let quoted = chars[i] == '"';
+        let mut token = String::new();
+        if quoted {
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None | Some('\n') => {
+                        return Err((line, "quoted string runs past the end of the line".into()))
+                    }
+                    Some('"') => break,
+                    Some('\\') => {
+                        token.push('\\');
+                        if let Some(&escaped) = chars.get(i + 1) {
+                            token.push(escaped);
+                            i += 1;
+                        }
+                    }
+                    Some(&c) => token.push(c),
+                }
+                i += 1;
+            }
+            // Skip the closing quote
+            i += 1;
+        } else {
+            while let Some(&c) = chars.get(i) {
+                match c {
+                    ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"' => break,
+                    '\\' => {
+                        token.push('\\');
+                        if let Some(&escaped) = chars.get(i + 1).filter(|c| **c != '\n') {
+                            token.push(escaped);
+                            i += 1;
+                        }
+                    }
+                    c => token.push(c),
+                }
+                i += 1;
+            }
+        }
+        tokens.push(Token {
+            text: token,
+            quoted,
+        });
+    }
+
+    if parentheses > 0 {
+        return Err((entry_line, "parenthesis is never closed".to_string()));
+    }
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            line: entry_line,
+            blank_owner: entry_blank_owner,
+            tokens,
+        });
+    }
+    Ok(entries)
+}
+
+/////////////////////////////////////////////////////
+// ENTRIES
+/////////////////////////////////////////////////////
+/// State carried from one entry of a file to the next.
+struct Parser<'a> {
+    path: &'a Path,
+    /// Files this one is nested in, counting itself.
+    depth: usize,
+    origin: DomainName,
+    /// Set by $TTL (RFC 2308 section 4).
+    default_ttl: Option<u32>,
+    /// Records without a TTL and without a $TTL take the last one given (RFC 1035).
+    last_ttl: Option<u32>,
+    last_owner: Option<DomainName>,
+    last_class: ResourceClass,
+}
+
+impl Parser<'_> {
+    fn error(&self, line: usize, message: String) -> ZoneError {
+        ZoneError::Syntax {
+            path: self.path.to_path_buf(),
+            line,
+            message,
+        }
+    }
+
+    fn entry(&mut self, entry: &Entry, records: &mut Vec<ResourceRecord>) -> Result<(), ZoneError> {
+        let first = &entry.tokens[0];
+        if !entry.blank_owner && !first.quoted && first.text.starts_with('$') {
+            return self.directive(entry, records);
+        }
+        let record = self
+            .record(entry)
+            .map_err(|message| self.error(entry.line, message))?;
+        records.push(record);
+        Ok(())
+    }
+
+    /// Handles `$ORIGIN`, `$TTL` and `$INCLUDE`.
+    fn directive(
+        &mut self,
+        entry: &Entry,
+        records: &mut Vec<ResourceRecord>,
+    ) -> Result<(), ZoneError> {
+        let directive = entry.tokens[0].text.to_ascii_uppercase();
+        let argument = |index: usize| {
+            entry
+                .tokens
+                .get(index)
+                .map(|token| token.text.as_str())
+                .ok_or_else(|| self.error(entry.line, format!("{} needs an argument", directive)))
+        };
+
+        match directive.as_str() {
+            "$ORIGIN" => {
+                let origin = parse_name(argument(1)?, &self.origin)
+                    .map_err(|message| self.error(entry.line, message))?;
+                self.origin = origin;
+            }
+            "$TTL" => {
+                let ttl =
+                    parse_ttl(argument(1)?).map_err(|message| self.error(entry.line, message))?;
+                self.default_ttl = Some(ttl);
+            }
+            "$INCLUDE" => {
+                // Relative paths are taken from the directory of the including file
+                let file = self
+                    .path
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(argument(1)?);
+                let origin = match entry.tokens.get(2) {
+                    Some(token) => parse_name(&token.text, &self.origin)
+                        .map_err(|message| self.error(entry.line, message))?,
+                    None => self.origin.clone(),
+                };
+                // The included file starts out with our default TTL, as in BIND
+                let default_ttl = self.default_ttl.or(self.last_ttl);
+                load_into(&file, &origin, default_ttl, self.depth + 1, records)?;
+            }
+            _ => {
+                return Err(self.error(
+                    entry.line,
+                    format!("unknown directive {}", entry.tokens[0].text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `[owner] [TTL] [class] type RDATA`, where TTL and class may come in either
+    /// order and missing fields are carried over from earlier entries.
+    fn record(&mut self, entry: &Entry) -> Result<ResourceRecord, String> {
+        let mut tokens = entry.tokens.as_slice();
+        let name = if entry.blank_owner {
+            self.last_owner
+                .clone()
+                .ok_or("first record has no owner name")?
+        } else {
+            let owner = parse_name(&tokens[0].text, &self.origin)?;
+            tokens = &tokens[1..];
+            owner
+        };
+
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.first() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text)?);
+            } else if let (None, Ok(parsed)) = (&class, ResourceClass::from_str(&token.text)) {
+                class = Some(parsed);
+            } else {
+                break;
+            }
+            tokens = &tokens[1..];
+        }
+
+        let resource_type_token = tokens.first().ok_or("record has no type")?;
+        let resource_type = ResourceType::from_str(&resource_type_token.text)?;
+        let resource_class = class.unwrap_or_else(|| self.last_class.clone());
+        let data = parse_rdata(&resource_type, &resource_class, &tokens[1..], &self.origin)?;
+
+        if ttl.is_some() {
+            self.last_ttl = ttl;
+        }
+        let ttl = ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .ok_or("record has no TTL and there is no $TTL before it")?;
+        self.last_owner = Some(name.clone());
+        self.last_class = resource_class.clone();
+
+        Ok(ResourceRecord {
+            name,
+            resource_class,
+            ttl,
+            data,
+        })
+    }
+}
+
+/////////////////////////////////////////////////////
+// RDATA
+/////////////////////////////////////////////////////
+/// Reads the RDATA fields of one record in presentation format.
+struct FieldReader<'a> {
+    tokens: &'a [Token],
+    origin: &'a DomainName,
+}
+
+impl<'a> FieldReader<'a> {
+    fn next(&mut self) -> Result<&'a Token, String> {
+        let (first, rest) = self
+            .tokens
+            .split_first()
+            .ok_or("record data has too few fields")?;
+        self.tokens = rest;
+        Ok(first)
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, String> {
+        let text = &self.next()?.text;
+        text.parse()
+            .map_err(|_| format!("{:?} is not a valid number here", text))
+    }
+
+    fn ttl(&mut self) -> Result<u32, String> {
+        parse_ttl(&self.next()?.text)
+    }
+
+    fn name(&mut self) -> Result<DomainName, String> {
+        parse_name(&self.next()?.text, self.origin)
+    }
+
+    fn character_string(&mut self) -> Result<Vec<u8>, String> {
+        let string = unescape(&self.next()?.text)?;
+        match string.len() {
+            0..=255 => Ok(string),
+            _ => Err("character string is longer than 255 bytes".to_string()),
+        }
+    }
+
+    /// The remaining fields as one hexadecimal string, which may be split by whitespace.
+    fn hex_rest(&mut self) -> Result<Vec<u8>, String> {
+        let hex: String = self
+            .tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect();
+        self.tokens = &[];
+        // Checked first so that slicing below never splits a multibyte character
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(format!("{:?} is not a hex string", hex));
+        }
+        if hex.len() % 2 != 0 {
+            return Err(format!("hex string {:?} has an odd length", hex));
+        }
+        Ok((0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked to be hex digits"))
+            .collect())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.tokens.first() {
+            Some(token) => Err(format!(
+                "unexpected field {:?} after record data",
+                token.text
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses the RDATA of a `resource_type` record from its fields, either in the type's own
+/// format or in the generic `\# length hex` one (RFC 3597 section 5).
+fn parse_rdata(
+    resource_type: &ResourceType,
+    resource_class: &ResourceClass,
+    tokens: &[Token],
+    origin: &DomainName,
+) -> Result<RData, String> {
+    let mut fields = FieldReader { tokens, origin };
+    if fields
+        .tokens
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        fields.next()?;
+        let length: usize = fields.number()?;
+        let data = fields.hex_rest()?;
+        if data.len() != length {
+            return Err(format!(
+                "generic RDATA is {} bytes, not {}",
+                data.len(),
+                length
+            ));
+        }
+        // Names in generic RDATA are never compressed, so the bytes stand on their own
+        return RData::deserialize(&data, 0, length, resource_type, resource_class)
+            .map_err(|e| e.to_string());
+    }
+
+    // Outside IN, types like A have a different layout we can't know
+    if *resource_class != ResourceClass::IN && resource_type.is_class_specific() {
+        return Err(format!(
+            "{} records in class {} are only supported in the generic \\# form",
+            resource_type, resource_class
+        ));
+    }
+
+    let data = match resource_type {
+        ResourceType::A => RData::A(parse_address::<Ipv4Addr>(fields.next()?)?),
+        ResourceType::AAAA => RData::AAAA(parse_address::<Ipv6Addr>(fields.next()?)?),
+        ResourceType::NS => RData::NS(fields.name()?),
+        ResourceType::MD => RData::MD(fields.name()?),
+        ResourceType::MF => RData::MF(fields.name()?),
+        ResourceType::CNAME => RData::CNAME(fields.name()?),
+        ResourceType::MB => RData::MB(fields.name()?),
+        ResourceType::MG => RData::MG(fields.name()?),
+        ResourceType::MR => RData::MR(fields.name()?),
+        ResourceType::PTR => RData::PTR(fields.name()?),
+        ResourceType::DNAME => RData::DNAME(fields.name()?),
+        ResourceType::SOA => RData::SOA {
+            mname: fields.name()?,
+            rname: fields.name()?,
+            serial: fields.number()?,
+            refresh: fields.ttl()?,
+            retry: fields.ttl()?,
+            expire: fields.ttl()?,
+            minimum: fields.ttl()?,
+        },
+        ResourceType::WKS => {
+            let address = parse_address::<Ipv4Addr>(fields.next()?)?;
+            let protocol = match fields.next()?.text.to_ascii_lowercase().as_str() {
+                "tcp" => 6,
+                "udp" => 17,
+                protocol => protocol
+                    .parse()
+                    .map_err(|_| format!("unknown protocol {:?}", protocol))?,
+            };
+            let mut bitmap = vec![];
+            while !fields.is_empty() {
+                let port: u16 = fields.number()?;
+                let index = port as usize / 8;
+                if bitmap.len() <= index {
+                    bitmap.resize(index + 1, 0);
+                }
+                bitmap[index] |= 0b1000_0000 >> (port % 8);
+            }
+            RData::WKS {
+                address,
+                protocol,
+                bitmap,
+            }
+        }
+        ResourceType::HINFO => RData::HINFO {
+            cpu: fields.character_string()?,
+            os: fields.character_string()?,
+        },
+        ResourceType::MINFO => RData::MINFO {
+            rmailbx: fields.name()?,
+            emailbx: fields.name()?,
+        },
+        ResourceType::MX => RData::MX {
+            preference: fields.number()?,
+            exchange: fields.name()?,
+        },
+        ResourceType::TXT => {
+            let mut strings = vec![fields.character_string()?];
+            while !fields.is_empty() {
+                strings.push(fields.character_string()?);
+            }
+            RData::TXT(strings)
+        }
+        ResourceType::SRV => RData::SRV {
+            priority: fields.number()?,
+            weight: fields.number()?,
+            port: fields.number()?,
+            target: fields.name()?,
+        },
+        ResourceType::NAPTR => RData::NAPTR {
+            order: fields.number()?,
+            preference: fields.number()?,
+            flags: fields.character_string()?,
+            services: fields.character_string()?,
+            regexp: fields.character_string()?,
+            replacement: fields.name()?,
+        },
+        ResourceType::SSHFP => RData::SSHFP {
+            algorithm: fields.number()?,
+            fingerprint_type: fields.number()?,
+            fingerprint: fields.hex_rest()?,
+        },
+        ResourceType::TLSA => RData::TLSA {
+            usage: fields.number()?,
+            selector: fields.number()?,
+            matching_type: fields.number()?,
+            data: fields.hex_rest()?,
+        },
+        ResourceType::CAA => RData::CAA {
+            flags: fields.number()?,
+            tag: fields.character_string()?,
+            // The value has no length byte on the wire, so it isn't limited to 255 bytes
+            value: unescape(&fields.next()?.text)?,
+        },
+        ResourceType::OPT => return Err("OPT records cannot appear in master files".into()),
+        resource_type => {
+            return Err(format!(
+                "{} records are only supported in the generic \\# form",
+                resource_type
+            ))
+        }
+    };
+    fields.finish()?;
+    Ok(data)
+}
+
+fn parse_address<T: FromStr>(token: &Token) -> Result<T, String> {
+    token
+        .text
+        .parse()
+        .map_err(|_| format!("{:?} is not a valid address", token.text))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn origin() -> DomainName {
+        parse_name("example.com.", &DomainName::new()).unwrap()
+    }
+
+    fn texts(entry: &Entry) -> Vec<&str> {
+        entry
+            .tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect()
+    }
+
+    /// Writes `files` into a directory of their own for the test named `test`.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zone-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn joins_lines_inside_parentheses_and_drops_comments() {
+        let text = "\
+@ IN SOA ns hostmaster ( ; the SOA spans lines
+        1 ; serial
+        3600 600 86400 300 )
+    A 192.0.2.1
+www A 192.0.2.2 ; trailing comment
+";
+        let entries = tokenize(text).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            texts(&entries[0]),
+            [
+                "@",
+                "IN",
+                "SOA",
+                "ns",
+                "hostmaster",
+                "1",
+                "3600",
+                "600",
+                "86400",
+                "300"
+            ]
+        );
+        assert_eq!((entries[0].line, entries[0].blank_owner), (1, false));
+        assert_eq!(texts(&entries[1]), ["A", "192.0.2.1"]);
+        assert_eq!((entries[1].line, entries[1].blank_owner), (4, true));
+        assert_eq!(texts(&entries[2]), ["www", "A", "192.0.2.2"]);
+        assert_eq!(entries[2].line, 5);
+    }
+
+    #[test]
+    fn keeps_quoted_strings_whole() {
+        let entries = tokenize(r#"@ TXT "a (b) ; \"c\"" plain"#).unwrap();
+        let tokens = &entries[0].tokens;
+        assert_eq!(tokens[2].text, r#"a (b) ; \"c\""#);
+        assert!(tokens[2].quoted);
+        assert_eq!(tokens[3].text, "plain");
+        assert!(!tokens[3].quoted);
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses_and_quotes() {
+        assert_eq!(tokenize("@ A (\n192.0.2.1").unwrap_err().0, 1);
+        assert_eq!(tokenize("@ A 192.0.2.1\n)").unwrap_err().0, 2);
+        assert!(tokenize("@ TXT \"open\n").is_err());
+    }
+
+    #[test]
+    fn applies_origin_and_ttl_directives() {
+        let dir = write_files(
+            "directives",
+            &[(
+                "zone",
+                "\
+$TTL 1h
+@ SOA ns hostmaster 1 2 3 4 5
+$ORIGIN sub
+www 60 A 192.0.2.1
+mail A 192.0.2.2
+",
+            )],
+        );
+        let records = load(&dir.join("zone"), &origin()).unwrap();
+        let owners: Vec<String> = records.iter().map(|r| r.name.to_string()).collect();
+        assert_eq!(
+            owners,
+            [
+                "example.com.",
+                "www.sub.example.com.",
+                "mail.sub.example.com."
+            ]
+        );
+        let ttls: Vec<u32> = records.iter().map(|r| r.ttl).collect();
+        assert_eq!(ttls, [3600, 60, 3600]);
+    }
+
+    #[test]
+    fn includes_files_relative_to_the_including_one() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "zone",
+                    "$TTL 300\n$INCLUDE hosts.inc sub\nwww A 192.0.2.1\n",
+                ),
+                ("hosts.inc", "host A 192.0.2.2\n"),
+            ],
+        );
+        let records = load(&dir.join("zone"), &origin()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name.to_string(), "host.sub.example.com.");
+        assert_eq!(records[0].ttl, 300);
+        // The origin set for the included file doesn't leak back out of it
+        assert_eq!(records[1].name.to_string(), "www.example.com.");
+    }
+
+    #[test]
+    fn rejects_includes_that_never_end() {
+        let dir = write_files("include-loop", &[("zone", "$INCLUDE zone\n")]);
+        assert!(matches!(
+            load(&dir.join("zone"), &origin()),
+            Err(ZoneError::IncludeTooDeep(MAX_INCLUDE_DEPTH))
+        ));
+    }
+
+    #[test]
+    fn parses_escapes_in_names() {
+        let name = parse_name(r"a\255b\.c.d", &origin()).unwrap();
+        assert_eq!(name.content[0], b"a\xffb.c");
+        assert_eq!(name.content[1], b"d");
+        assert_eq!(name.content.len(), 4);
+        assert!(parse_name(r"a\256", &origin()).is_err());
+        assert!(parse_name("a..b", &origin()).is_err());
+    }
+
+    fn rdata(resource_type: ResourceType, text: &str) -> Result<RData, String> {
+        let entries = tokenize(text).map_err(|(_, message)| message)?;
+        parse_rdata(
+            &resource_type,
+            &ResourceClass::IN,
+            &entries[0].tokens,
+            &origin(),
+        )
+    }
+
+    #[test]
+    fn parses_generic_rdata() {
+        assert_eq!(
+            rdata(ResourceType::A, r"\# 4 c0 00 02 01"),
+            Ok(RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+        assert!(rdata(ResourceType::A, r"\# 4 c00002").is_err());
+    }
+
+    #[test]
+    fn rejects_generic_rdata_that_is_not_hex() {
+        assert!(rdata(ResourceType::Unknown(999), r"\# 2 aéb").is_err());
+        assert!(rdata(ResourceType::Unknown(999), r"\# 2 éa").is_err());
+        assert!(rdata(ResourceType::Unknown(999), r"\# 1 +1").is_err());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod authority;
+pub mod catalog;
+pub mod master_file;
+pub mod zone_error;
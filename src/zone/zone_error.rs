@@ -0,0 +1,20 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+/// Everything that can go wrong while loading a zone.
+#[derive(Debug, Error)]
+pub enum ZoneError {
+    #[error("could not read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}:{line}: {message}", path.display())]
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("$INCLUDE nested more than {0} files deep")]
+    IncludeTooDeep(usize),
+    #[error("zone {0} has no SOA record at its apex")]
+    MissingSoa(String),
+}